@@ -76,6 +76,63 @@ extern "C" {
     pub fn hv_vm_destroy() -> hv_return_t;
 }
 
+#[cfg(feature = "macos-13-0")]
+extern "C" {
+    /// Returns the maximum intermediate physical address bit length.
+    ///
+    /// # Parameters
+    ///
+    /// * `ipa_bit_length`: Pointer to bit length (written on success).
+    ///
+    /// # Return Value
+    ///
+    /// `HV_SUCCESS` if the operation was successful, otherwise an error code specified in
+    /// [`hv_return_t`].
+    pub fn hv_vm_config_get_max_ipa_size(ipa_bit_length: *mut u32) -> hv_return_t;
+
+    /// Returns the default intermediate physical address bit length.
+    ///
+    /// # Parameters
+    ///
+    /// * `ipa_bit_length`: Pointer to bit length (written on success).
+    ///
+    /// # Return Value
+    ///
+    /// `HV_SUCCESS` if the operation was successful, otherwise an error code specified in
+    /// [`hv_return_t`].
+    pub fn hv_vm_config_get_default_ipa_size(ipa_bit_length: *mut u32) -> hv_return_t;
+}
+
+#[cfg(feature = "macos-15-0")]
+extern "C" {
+    /// Returns whether EL2 is supported on the current platform.
+    ///
+    /// # Parameters
+    ///
+    /// * `el2_supported`: Pointer to whether EL2 is supported (written on success).
+    ///
+    /// # Return Value
+    ///
+    /// `HV_SUCCESS` if the operation was successful, otherwise an error code specified in
+    /// [`hv_return_t`].
+    pub fn hv_vm_config_get_el2_supported(el2_supported: *mut bool) -> hv_return_t;
+}
+
+#[cfg(feature = "macos-15-2")]
+extern "C" {
+    /// Returns the maximum Streaming Vector Length (SVL) in bytes.
+    ///
+    /// # Parameters
+    ///
+    /// * `value`: Pointer to the maximum SVL in bytes (written on success).
+    ///
+    /// # Return Value
+    ///
+    /// `HV_SUCCESS` if the operation was successful, `HV_UNSUPPORTED` if SME is not supported,
+    /// otherwise an error code specified in [`hv_return_t`].
+    pub fn hv_sme_config_get_max_svl_bytes(value: *mut usize) -> hv_return_t;
+}
+
 // -----------------------------------------------------------------------------------------------
 // vCPU Management - Configuration
 // -----------------------------------------------------------------------------------------------
@@ -748,6 +805,9 @@ pub enum hv_sys_reg_t {
     HV_SYS_REG_ID_AA64MMFR2_EL1 = 0xc03a,
     /// The value that represents the system register SCTLR_EL1.
     HV_SYS_REG_SCTLR_EL1 = 0xc080,
+    /// The value that represents the system register ACTLR_EL1.
+    #[cfg(feature = "macos-15-0")]
+    HV_SYS_REG_ACTLR_EL1 = 0xc081,
     /// The value that represents the system register CPACR_EL1.
     HV_SYS_REG_CPACR_EL1 = 0xc082,
     /// The value that represents the system register TTBR0_EL1.
@@ -980,6 +1040,19 @@ extern "C" {
     pub fn hv_vm_protect(ipa: hv_ipa_t, size: usize, flags: hv_memory_flags_t) -> hv_return_t;
 }
 
+extern "C" {
+    /// Invalidates the instruction cache for a range of the address space of the current process.
+    ///
+    /// This function is provided by the system library rather than by `Hypervisor.framework`.
+    /// It must be called after writing code into memory mapped in the guest.
+    ///
+    /// # Parameters
+    ///
+    /// * `start`: The host address of the range.
+    /// * `len`: The size of the range, in bytes.
+    pub fn sys_icache_invalidate(start: *mut c_void, len: usize);
+}
+
 // -----------------------------------------------------------------------------------------------
 // Timer Functions
 // -----------------------------------------------------------------------------------------------
@@ -1039,6 +1112,18 @@ extern "C" {
     pub fn hv_vcpu_set_vtimer_offset(vcpu: hv_vcpu_t, vtimer_offset: u64) -> hv_return_t;
 }
 
+extern "C" {
+    /// Returns the current value of the host's counter, in ticks.
+    ///
+    /// This function is provided by the system library rather than by `Hypervisor.framework`.
+    /// It is the counter the vTimer offset applies to.
+    ///
+    /// # Return Value
+    ///
+    /// The current value of the counter.
+    pub fn mach_absolute_time() -> u64;
+}
+
 #[cfg(test)]
 mod tests {
     // Tests must be run with `--test-threads=1`, since only one VM instance is allowed per
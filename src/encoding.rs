@@ -0,0 +1,88 @@
+//! Helpers to encode common AArch64 instructions.
+//!
+//! These functions are meant to build small code snippets, trampolines or patches that can then
+//! be written into guest memory using [`Mappable::write_dword`](crate::Mappable::write_dword).
+
+use crate::{HypervisorError, Reg, Result};
+
+/// Returns the 26-bit immediate of a branch from `from` to `to`.
+fn branch_imm26(from: u64, to: u64) -> Result<u32> {
+    let offset = to.wrapping_sub(from) as i64;
+    // The offset must be 4-byte aligned and fit in a signed 28-bit value.
+    if offset % 4 != 0 || !(-(1 << 27)..(1 << 27)).contains(&offset) {
+        return Err(HypervisorError::BadArgument);
+    }
+    Ok((offset >> 2) as u32 & 0x3ffffff)
+}
+
+/// Encodes a `b to` instruction located at address `from`.
+///
+/// Returns [`HypervisorError::BadArgument`] if the target is not 4-byte aligned or out of the
+/// ±128MB range of the instruction.
+pub fn encode_b(from: u64, to: u64) -> Result<u32> {
+    Ok(0x14000000 | branch_imm26(from, to)?)
+}
+
+/// Encodes a `bl to` instruction located at address `from`.
+///
+/// Returns [`HypervisorError::BadArgument`] if the target is not 4-byte aligned or out of the
+/// ±128MB range of the instruction.
+pub fn encode_bl(from: u64, to: u64) -> Result<u32> {
+    Ok(0x94000000 | branch_imm26(from, to)?)
+}
+
+/// Encodes a `movz rd, #imm, lsl #shift` instruction.
+///
+/// Returns [`HypervisorError::BadArgument`] if `rd` is not one of X0 to X30 or if `shift` is
+/// not 0, 16, 32 or 48.
+pub fn encode_movz(rd: Reg, imm: u16, shift: u32) -> Result<u32> {
+    if rd > Reg::X30 || !matches!(shift, 0 | 16 | 32 | 48) {
+        return Err(HypervisorError::BadArgument);
+    }
+    Ok(0xd2800000 | ((shift / 16) << 21) | ((imm as u32) << 5) | rd as u32)
+}
+
+/// Encodes a `brk #imm` instruction.
+pub fn encode_brk(imm: u16) -> u32 {
+    0xd4200000 | ((imm as u32) << 5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_branches() {
+        // Forward and backward branches.
+        assert_eq!(encode_b(0x4000, 0x4100), Ok(0x14000040));
+        assert_eq!(encode_b(0x10000, 0x4100), Ok(0x17ffd040));
+        assert_eq!(encode_bl(0x4000, 0x4100), Ok(0x94000040));
+        assert_eq!(encode_bl(0x4100, 0x4000), Ok(0x97ffffc0));
+        // Branching to itself.
+        assert_eq!(encode_b(0x4000, 0x4000), Ok(0x14000000));
+        // Range limits.
+        assert_eq!(encode_b(0, 0x7fffffc), Ok(0x15ffffff));
+        assert_eq!(encode_b(0x8000000, 0), Ok(0x16000000));
+        assert_eq!(encode_b(0, 0x8000000), Err(HypervisorError::BadArgument));
+        assert_eq!(encode_bl(0x8000004, 0), Err(HypervisorError::BadArgument));
+        // Unaligned targets.
+        assert_eq!(encode_b(0x4000, 0x4002), Err(HypervisorError::BadArgument));
+    }
+
+    #[test]
+    fn encode_movz_brk() {
+        assert_eq!(encode_movz(Reg::X0, 0x42, 0), Ok(0xd2800840));
+        assert_eq!(encode_movz(Reg::X1, 0x2, 0), Ok(0xd2800041));
+        assert_eq!(encode_movz(Reg::X30, 0xffff, 48), Ok(0xd2fffffe));
+        assert_eq!(
+            encode_movz(Reg::X0, 0, 8),
+            Err(HypervisorError::BadArgument)
+        );
+        assert_eq!(
+            encode_movz(Reg::PC, 0, 0),
+            Err(HypervisorError::BadArgument)
+        );
+        assert_eq!(encode_brk(0), 0xd4200000);
+        assert_eq!(encode_brk(0xcafe), 0xd4395fc0);
+    }
+}
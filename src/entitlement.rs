@@ -0,0 +1,59 @@
+//! Detection of the hypervisor entitlement.
+//!
+//! Binaries must be codesigned with the `com.apple.security.hypervisor` entitlement to use the
+//! Hypervisor framework, otherwise creating a virtual machine fails with
+//! [`HypervisorError::Denied`](crate::HypervisorError::Denied). The probe below allows to fail
+//! early with a clear message instead.
+
+use core::ptr;
+use core::sync::atomic::Ordering;
+
+use applevisor_sys::{hv_error_t, hv_return_t, hv_vm_create, hv_vm_destroy};
+
+use crate::VM_ALIVE;
+
+/// Returns whether the current binary is allowed to use the Hypervisor framework.
+///
+/// If the process already has a virtual machine, `true` is returned right away. Otherwise, the
+/// check is performed by creating a virtual machine and destroying it right away, which means
+/// it must not run concurrently with [`VirtualMachine::new`](crate::VirtualMachine::new). Only
+/// [`HypervisorError::Denied`](crate::HypervisorError::Denied) is reported as `false`, other
+/// failures do not relate to the entitlement.
+///
+/// # Panics
+///
+/// Panics if the virtual machine created for the check can't be destroyed.
+pub fn has_hypervisor_entitlement() -> bool {
+    if VM_ALIVE.load(Ordering::SeqCst) {
+        return true;
+    }
+    let ret = unsafe { hv_vm_create(ptr::null_mut()) };
+    if ret == hv_error_t::HV_SUCCESS as hv_return_t {
+        let ret = unsafe { hv_vm_destroy() };
+        assert_eq!(
+            ret,
+            hv_error_t::HV_SUCCESS as hv_return_t,
+            "Could not properly destroy VM context"
+        );
+    }
+    ret != hv_error_t::HV_DENIED as hv_return_t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtualMachine;
+
+    #[test]
+    fn entitlement_probe() {
+        // Tests are run from entitled binaries.
+        assert!(has_hypervisor_entitlement());
+        // The probe does not leave a virtual machine behind...
+        let vm = VirtualMachine::new().unwrap();
+        // ... nor destroys an existing one.
+        assert!(has_hypervisor_entitlement());
+        assert!(vm
+            .create_mapping(0x4000, 0x1000, crate::MemPerms::RW)
+            .is_ok());
+    }
+}
@@ -0,0 +1,235 @@
+//! Helpers to decode exception syndromes, as found in ESR_EL1 or in the exit information of a
+//! vCPU.
+//!
+//! These are the primitives used to extract the different fields of a syndrome returned by
+//! [`VcpuExit`](crate::VcpuExit).
+
+/// Position of the Exception Class (EC) field in a syndrome.
+pub const EC_SHIFT: u32 = 26;
+
+/// Mask of the Exception Class (EC) field in a syndrome.
+pub const EC_MASK: u64 = 0x3f << EC_SHIFT;
+
+/// Mask of the Instruction Length (IL) bit in a syndrome.
+pub const IL_MASK: u64 = 1 << 25;
+
+/// Mask of the Instruction Specific Syndrome (ISS) field in a syndrome.
+pub const ISS_MASK: u64 = IL_MASK - 1;
+
+/// Position of the second Instruction Specific Syndrome (ISS2) field in a syndrome.
+pub const ISS2_SHIFT: u32 = 32;
+
+/// Mask of the second Instruction Specific Syndrome (ISS2) field in a syndrome.
+pub const ISS2_MASK: u64 = 0xff_ffff << ISS2_SHIFT;
+
+/// Returns the Exception Class (EC) of `syndrome`.
+pub fn ec(syndrome: u64) -> u8 {
+    ((syndrome & EC_MASK) >> EC_SHIFT) as u8
+}
+
+/// Returns the Instruction Specific Syndrome (ISS) of `syndrome`.
+pub fn iss(syndrome: u64) -> u32 {
+    (syndrome & ISS_MASK) as u32
+}
+
+/// Returns whether `syndrome` was caused by a 32-bit instruction, according to its Instruction
+/// Length (IL) bit.
+pub fn il(syndrome: u64) -> bool {
+    syndrome & IL_MASK != 0
+}
+
+/// Represents the Exception Class (EC) of a syndrome.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ExceptionClass {
+    /// Unknown reason.
+    Unknown,
+    /// Trapped `WFI` or `WFE` instruction.
+    Wfx,
+    /// Access to SVE, Advanced SIMD or floating-point functionality.
+    SimdFpAccess,
+    /// Illegal execution state.
+    IllegalExecutionState,
+    /// `SVC` instruction executed in AArch64 state.
+    Svc,
+    /// `HVC` instruction executed in AArch64 state.
+    Hvc,
+    /// `SMC` instruction executed in AArch64 state.
+    Smc,
+    /// Trapped `MSR`, `MRS` or system instruction.
+    SysRegAccess,
+    /// Access to SVE functionality.
+    SveAccess,
+    /// Pointer authentication failure.
+    PointerAuth,
+    /// Instruction abort taken from a lower exception level.
+    InstructionAbortLowerEl,
+    /// Instruction abort taken without a change of exception level.
+    InstructionAbortSameEl,
+    /// PC alignment fault.
+    PcAlignment,
+    /// Data abort taken from a lower exception level.
+    DataAbortLowerEl,
+    /// Data abort taken without a change of exception level.
+    DataAbortSameEl,
+    /// SP alignment fault.
+    SpAlignment,
+    /// Trapped floating-point exception in AArch64 state.
+    FpException,
+    /// SError interrupt.
+    SError,
+    /// Breakpoint exception taken from a lower exception level.
+    BreakpointLowerEl,
+    /// Breakpoint exception taken without a change of exception level.
+    BreakpointSameEl,
+    /// Software step exception taken from a lower exception level.
+    SoftwareStepLowerEl,
+    /// Software step exception taken without a change of exception level.
+    SoftwareStepSameEl,
+    /// Watchpoint exception taken from a lower exception level.
+    WatchpointLowerEl,
+    /// Watchpoint exception taken without a change of exception level.
+    WatchpointSameEl,
+    /// `BRK` instruction executed in AArch64 state.
+    Brk,
+    /// Any other exception class, with its raw value.
+    Other(u8),
+}
+
+impl From<u8> for ExceptionClass {
+    fn from(ec: u8) -> Self {
+        match ec {
+            0x00 => Self::Unknown,
+            0x01 => Self::Wfx,
+            0x07 => Self::SimdFpAccess,
+            0x0e => Self::IllegalExecutionState,
+            0x15 => Self::Svc,
+            0x16 => Self::Hvc,
+            0x17 => Self::Smc,
+            0x18 => Self::SysRegAccess,
+            0x19 => Self::SveAccess,
+            0x1c => Self::PointerAuth,
+            0x20 => Self::InstructionAbortLowerEl,
+            0x21 => Self::InstructionAbortSameEl,
+            0x22 => Self::PcAlignment,
+            0x24 => Self::DataAbortLowerEl,
+            0x25 => Self::DataAbortSameEl,
+            0x26 => Self::SpAlignment,
+            0x2c => Self::FpException,
+            0x2f => Self::SError,
+            0x30 => Self::BreakpointLowerEl,
+            0x31 => Self::BreakpointSameEl,
+            0x32 => Self::SoftwareStepLowerEl,
+            0x33 => Self::SoftwareStepSameEl,
+            0x34 => Self::WatchpointLowerEl,
+            0x35 => Self::WatchpointSameEl,
+            0x3c => Self::Brk,
+            _ => Self::Other(ec),
+        }
+    }
+}
+
+impl ExceptionClass {
+    /// Returns the raw value of the exception class.
+    pub fn raw(&self) -> u8 {
+        match self {
+            Self::Unknown => 0x00,
+            Self::Wfx => 0x01,
+            Self::SimdFpAccess => 0x07,
+            Self::IllegalExecutionState => 0x0e,
+            Self::Svc => 0x15,
+            Self::Hvc => 0x16,
+            Self::Smc => 0x17,
+            Self::SysRegAccess => 0x18,
+            Self::SveAccess => 0x19,
+            Self::PointerAuth => 0x1c,
+            Self::InstructionAbortLowerEl => 0x20,
+            Self::InstructionAbortSameEl => 0x21,
+            Self::PcAlignment => 0x22,
+            Self::DataAbortLowerEl => 0x24,
+            Self::DataAbortSameEl => 0x25,
+            Self::SpAlignment => 0x26,
+            Self::FpException => 0x2c,
+            Self::SError => 0x2f,
+            Self::BreakpointLowerEl => 0x30,
+            Self::BreakpointSameEl => 0x31,
+            Self::SoftwareStepLowerEl => 0x32,
+            Self::SoftwareStepSameEl => 0x33,
+            Self::WatchpointLowerEl => 0x34,
+            Self::WatchpointSameEl => 0x35,
+            Self::Brk => 0x3c,
+            Self::Other(ec) => *ec,
+        }
+    }
+
+    /// Returns a short description of the exception class.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown reason",
+            Self::Wfx => "trapped WFI/WFE",
+            Self::SimdFpAccess => "SVE/SIMD/FP access",
+            Self::IllegalExecutionState => "illegal execution state",
+            Self::Svc => "SVC (AArch64)",
+            Self::Hvc => "HVC (AArch64)",
+            Self::Smc => "SMC (AArch64)",
+            Self::SysRegAccess => "trapped MSR/MRS/system instruction",
+            Self::SveAccess => "SVE access",
+            Self::PointerAuth => "pointer authentication failure",
+            Self::InstructionAbortLowerEl => "instruction abort (lower EL)",
+            Self::InstructionAbortSameEl => "instruction abort (same EL)",
+            Self::PcAlignment => "PC alignment fault",
+            Self::DataAbortLowerEl => "data abort (lower EL)",
+            Self::DataAbortSameEl => "data abort (same EL)",
+            Self::SpAlignment => "SP alignment fault",
+            Self::FpException => "floating-point exception (AArch64)",
+            Self::SError => "SError interrupt",
+            Self::BreakpointLowerEl => "breakpoint (lower EL)",
+            Self::BreakpointSameEl => "breakpoint (same EL)",
+            Self::SoftwareStepLowerEl => "software step (lower EL)",
+            Self::SoftwareStepSameEl => "software step (same EL)",
+            Self::WatchpointLowerEl => "watchpoint (lower EL)",
+            Self::WatchpointSameEl => "watchpoint (same EL)",
+            Self::Brk => "BRK (AArch64)",
+            Self::Other(_) => "unknown exception class",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_syndromes() {
+        // `brk #0x10`
+        assert_eq!(ec(0xf200_0010), 0x3c);
+        assert_eq!(iss(0xf200_0010), 0x10);
+        assert!(il(0xf200_0010));
+        // Translation fault, level 3, on a write access.
+        assert_eq!(ec(0x9200_0047), 0x24);
+        assert_eq!(iss(0x9200_0047), 0x47);
+        assert!(il(0x9200_0047));
+        // Reserved bits are ignored.
+        assert_eq!(ec(0x0000_0010_5a00_0000), 0x16);
+        assert_eq!(iss(0x0000_0010_5a00_0000), 0);
+        // Unknown reason, with a cleared IL bit.
+        assert_eq!(ec(0), 0);
+        assert!(!il(0x01ff_ffff));
+        assert_eq!(iss(0x01ff_ffff), 0x1ff_ffff);
+    }
+
+    #[test]
+    fn exception_classes() {
+        assert_eq!(ExceptionClass::from(ec(0xf200_0010)), ExceptionClass::Brk);
+        assert_eq!(ExceptionClass::from(0x24), ExceptionClass::DataAbortLowerEl);
+        assert_eq!(ExceptionClass::from(0x3f), ExceptionClass::Other(0x3f));
+        // Conversions are lossless, including for unlisted exception classes.
+        for raw in 0..=0x3f {
+            assert_eq!(ExceptionClass::from(raw).raw(), raw);
+        }
+        assert_eq!(ExceptionClass::Svc.name(), "SVC (AArch64)");
+        assert_eq!(
+            ExceptionClass::Other(0x3f).name(),
+            "unknown exception class"
+        );
+    }
+}
@@ -129,11 +129,12 @@
 
 #![cfg_attr(feature = "simd_nightly", feature(portable_simd), feature(simd_ffi), feature(concat_idents))]
 
+use core::cmp::Ordering;
 use core::ffi::c_void;
 use core::ptr;
 use std::alloc;
 use std::hash::{Hash, Hasher};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
 #[cfg(feature = "simd_nightly")]
 use std::simd;
@@ -150,20 +151,76 @@ use applevisor_sys::hv_simd_fp_reg_t::*;
 use applevisor_sys::hv_sys_reg_t::*;
 use applevisor_sys::*;
 
+pub mod encoding;
+pub mod entitlement;
+pub mod esr;
+pub mod loader;
+pub mod mmio;
+pub mod mmu;
+pub mod sched;
+
 // -----------------------------------------------------------------------------------------------
 // Macros
 // -----------------------------------------------------------------------------------------------
 
 /// Macro that calls an ffi hypervisor function and wraps the resulting return value in a
 /// [`Result`].
+///
+/// When the `log` feature is enabled, the function name, its arguments and its return value are
+/// logged at the trace level.
 macro_rules! hv_unsafe_call {
-    ($x:expr) => {{
-        let ret = unsafe { $x };
+    // Binds the arguments to locals one at a time, so that each of them is evaluated only once
+    // even though it is also logged. Hygiene keeps the `arg` locals of each expansion distinct.
+    (@bind $f:ident, [$($bound:ident = $orig:expr,)*], $arg:expr, $($rest:expr,)*) => {
+        hv_unsafe_call!(@bind $f, [$($bound = $orig,)* arg = $arg,], $($rest,)*)
+    };
+    (@bind $f:ident, [$($bound:ident = $orig:expr,)*],) => {{
+        $(let $bound = $orig;)*
+        let ret = unsafe { $f($($bound),*) };
+        #[cfg(feature = "log")]
+        if log::log_enabled!(log::Level::Trace) {
+            let args: &[String] = &[$(format!(concat!(stringify!($orig), "={:?}"), $bound)),*];
+            log::trace!("{}({}) -> {:#x}", stringify!($f), args.join(", "), ret);
+        }
         match ret {
             x if x == hv_error_t::HV_SUCCESS as i32 => Ok(()),
             code => Err(HypervisorError::from(code)),
         }
     }};
+    ($f:ident($($arg:expr),* $(,)?)) => {
+        hv_unsafe_call!(@bind $f, [], $($arg,)*)
+    };
+}
+
+/// Macro that generates the accessors of a register wrapper `$reg` around a raw `u64` value.
+///
+/// Each bit `$bit`, which must be an associated constant of `$reg` holding its mask, gets a
+/// getter `$get` and a setter `$set`.
+macro_rules! gen_bit_accessors {
+    ($reg:ident, $($bit:ident: $get:ident, $set:ident;)*) => {
+        impl $reg {
+            /// Returns the raw value of the register.
+            pub fn bits(&self) -> u64 {
+                self.0
+            }
+
+            $(
+                #[doc = concat!("Returns the value of the ", stringify!($bit), " bit.")]
+                pub fn $get(&self) -> bool {
+                    self.0 & Self::$bit != 0
+                }
+
+                #[doc = concat!("Sets the value of the ", stringify!($bit), " bit.")]
+                pub fn $set(&mut self, enabled: bool) {
+                    if enabled {
+                        self.0 |= Self::$bit;
+                    } else {
+                        self.0 &= !Self::$bit;
+                    }
+                }
+            )*
+        }
+    };
 }
 
 /// Macro that generates an enum `$dst` corresponding to the raw C enum `$src`.
@@ -174,24 +231,35 @@ macro_rules! gen_enum {
         $(#[$cmt:meta])* $dst: ident,
         $src: ident,
         $prefix:ident,
-        $(#[$var_cmt:meta] $variant: ident,)*
+        $($(#[$var_cmt:meta])* $variant: ident,)*
     ) => {
         $(#[$cmt])*
         #[allow(non_camel_case_types)]
         #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
         pub enum $dst {
             $(
-                #[$var_cmt]
+                $(#[$var_cmt])*
                 $variant,
             )*
         }
 
+        impl $dst {
+            /// Returns all the values of the enum, in declaration order.
+            // Variant attributes are forwarded to the array elements for `cfg` to apply.
+            #[allow(unused_doc_comments)]
+            pub fn all() -> &'static [Self] {
+                &[$($(#[$var_cmt])* Self::$variant,)*]
+            }
+        }
+
         #[cfg(feature = "simd_nightly")]
         #[allow(clippy::from_over_into)]
         impl Into<$src> for $dst {
+            // Variant attributes are forwarded to the match arms for `cfg` to apply.
+            #[allow(unused_doc_comments)]
             fn into(self) -> $src {
                 match self {
-                    $($dst::$variant => concat_idents!($prefix, $variant),)*
+                    $($(#[$var_cmt])* $dst::$variant => concat_idents!($prefix, $variant),)*
                 }
             }
         }
@@ -199,9 +267,14 @@ macro_rules! gen_enum {
         #[cfg(not(feature = "simd_nightly"))]
         #[allow(clippy::from_over_into)]
         impl Into<$src> for $dst {
+            // Variant attributes are forwarded to the match arms for `cfg` to apply.
+            #[allow(unused_doc_comments)]
             fn into(self) -> $src {
                 match self {
-                    $($dst::$variant => concat_idents!(x = $prefix, $variant { x }),)*
+                    $(
+                        $(#[$var_cmt])*
+                        $dst::$variant => concat_idents!(x = $prefix, $variant { x }),
+                    )*
                 }
             }
         }
@@ -607,6 +680,9 @@ gen_enum!(
     ID_AA64MMFR2_EL1,
     /// The value that represents the system register SCTLR_EL1.
     SCTLR_EL1,
+    /// The value that represents the system register ACTLR_EL1.
+    #[cfg(feature = "macos-15-0")]
+    ACTLR_EL1,
     /// The value that represents the system register CPACR_EL1.
     CPACR_EL1,
     /// The value that represents the system register TTBR0_EL1.
@@ -703,8 +779,20 @@ pub enum HypervisorError {
     NoDevice,
     /// No host resources available to complete the request.
     NoResources,
+    /// The memory mapping is not currently mapped in the guest.
+    ///
+    /// This error is raised by the crate itself and does not originate from the framework.
+    NotMapped,
+    /// The memory access falls outside of the memory mapping.
+    ///
+    /// This error is raised by the crate itself and does not originate from the framework.
+    OutOfBounds,
     /// An unknown error type.
     Unknown(hv_return_t),
+    /// The address or size provided is not properly aligned.
+    ///
+    /// This error is raised by the crate itself and does not originate from the framework.
+    Unaligned,
     /// The operation is not supported.
     Unsupported,
 }
@@ -721,10 +809,35 @@ impl HypervisorError {
             Self::IllegalState => "guest in an illegal state",
             Self::NoDevice => "no VM or vCPU available",
             Self::NoResources => "no host resources available to complete the request",
+            Self::NotMapped => "memory mapping not mapped in the guest",
+            Self::OutOfBounds => "memory access outside of the memory mapping",
+            Self::Unaligned => "address or size not properly aligned",
             Self::Unknown(_) => "unknown error",
             Self::Unsupported => "unsupported operation",
         }
     }
+
+    /// Returns the raw framework error code corresponding to the error.
+    ///
+    /// Errors raised by the crate itself are reported as the framework error they used to be
+    /// returned as.
+    pub fn code(&self) -> hv_return_t {
+        (*self).into()
+    }
+
+    /// Returns a hint about the likely cause of the error, when one is known.
+    ///
+    /// For example, [`HypervisorError::Denied`] is returned when creating a virtual machine from
+    /// a binary that was not signed with the hypervisor entitlement.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::Denied => Some(
+                "the Hypervisor framework can only be used by binaries codesigned with the \
+                 com.apple.security.hypervisor entitlement",
+            ),
+            _ => None,
+        }
+    }
 }
 
 impl From<hv_return_t> for HypervisorError {
@@ -756,6 +869,10 @@ impl Into<hv_return_t> for HypervisorError {
             Self::IllegalState => hv_error_t::HV_ILLEGAL_GUEST_STATE as hv_return_t,
             Self::NoDevice => hv_error_t::HV_NO_DEVICE as hv_return_t,
             Self::NoResources => hv_error_t::HV_NO_RESOURCES as hv_return_t,
+            // Crate-level errors are reported as the framework error they used to be returned as.
+            Self::NotMapped => hv_error_t::HV_ERROR as hv_return_t,
+            Self::OutOfBounds => hv_error_t::HV_BAD_ARGUMENT as hv_return_t,
+            Self::Unaligned => hv_error_t::HV_BAD_ARGUMENT as hv_return_t,
             Self::Unsupported => hv_error_t::HV_UNSUPPORTED as hv_return_t,
             Self::Unknown(code) => code,
         }
@@ -766,19 +883,18 @@ impl std::error::Error for HypervisorError {}
 
 impl core::fmt::Display for HypervisorError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(
-            f,
-            "{} (error {:#08x})",
-            self.as_str(),
-            Into::<hv_return_t>::into(*self)
-        )
+        write!(f, "{} (error {:#08x})", self.as_str(), self.code())?;
+        match self.hint() {
+            Some(hint) => write!(f, ", {}", hint),
+            None => Ok(()),
+        }
     }
 }
 
 impl core::fmt::Debug for HypervisorError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("HypervisorError")
-            .field("code", &Into::<hv_return_t>::into(*self))
+            .field("code", &self.code())
             .field("description", &self.as_str())
             .finish()
     }
@@ -788,6 +904,146 @@ impl core::fmt::Debug for HypervisorError {
 // Virtual Machine
 // -----------------------------------------------------------------------------------------------
 
+/// Whether the virtual machine of the current process is alive.
+static VM_ALIVE: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// First guest address returned by [`VirtualMachine::next_guest_addr`].
+const FIRST_GUEST_ADDR: u64 = 0x1000_0000;
+
+/// End of the guest address range used by [`VirtualMachine::next_guest_addr`], which is the
+/// default intermediate physical address size of 36 bits.
+const GUEST_ADDR_LIMIT: u64 = 1 << 36;
+
+/// Next guest address returned by [`VirtualMachine::next_guest_addr`].
+static NEXT_GUEST_ADDR: core::sync::atomic::AtomicU64 =
+    core::sync::atomic::AtomicU64::new(FIRST_GUEST_ADDR);
+
+/// Identifiers of the vCPUs that are currently alive in the process.
+static LIVE_VCPUS: Mutex<Vec<hv_vcpu_t>> = Mutex::new(Vec::new());
+
+/// A guest address range reserved using [`VirtualMachine::reserve_range`].
+struct ReservedRange {
+    range: core::ops::Range<u64>,
+    /// Whether memory is forbidden from being mapped inside the range, e.g. for MMIO regions.
+    exclusive: bool,
+}
+
+/// Guest address ranges currently reserved in the process.
+static RESERVATIONS: Mutex<Vec<ReservedRange>> = Mutex::new(Vec::new());
+
+/// Guest address ranges currently backed by memory mapped using the crate.
+///
+/// Ranges are only added while [`RESERVATIONS`] is locked, so that exclusive reservations cannot
+/// be made over memory being mapped.
+static MAPPED_RANGES: Mutex<Vec<core::ops::Range<u64>>> = Mutex::new(Vec::new());
+
+/// Records that the `size` bytes starting at `guest_addr` are now backed by memory.
+///
+/// Must be called while holding the guard returned by [`check_reservations`].
+fn record_mapped_range(guest_addr: u64, size: usize) {
+    MAPPED_RANGES
+        .lock()
+        .unwrap()
+        .push(guest_addr..guest_addr + size as u64);
+}
+
+/// Records that the `size` bytes starting at `guest_addr` are no longer backed by memory.
+fn forget_mapped_range(guest_addr: u64, size: usize) {
+    let range = guest_addr..guest_addr + size as u64;
+    let mut mapped_ranges = MAPPED_RANGES.lock().unwrap();
+    if let Some(index) = mapped_ranges.iter().position(|r| *r == range) {
+        mapped_ranges.swap_remove(index);
+    }
+}
+
+/// Checks that the guest range of `size` bytes starting at `guest_addr` is either entirely
+/// contained in a non-exclusive reservation or does not overlap with any.
+///
+/// The returned guard keeps the reservations locked, and must be held until the range is mapped
+/// so that no reservation can be made in between.
+fn check_reservations(
+    guest_addr: u64,
+    size: usize,
+) -> Result<std::sync::MutexGuard<'static, Vec<ReservedRange>>> {
+    let end = guest_addr
+        .checked_add(size as u64)
+        .ok_or(HypervisorError::BadArgument)?;
+    let reservations = RESERVATIONS.lock().unwrap();
+    let overlapping = reservations
+        .iter()
+        .find(|r| guest_addr < r.range.end && r.range.start < end);
+    match overlapping {
+        Some(r) if r.exclusive => Err(HypervisorError::Busy),
+        Some(r) if guest_addr < r.range.start || end > r.range.end => Err(HypervisorError::Busy),
+        _ => Ok(reservations),
+    }
+}
+
+/// Reserves the guest address range of `size` bytes starting at `guest_addr`.
+///
+/// Memory cannot be mapped anywhere in the range if the reservation is `exclusive`, which is why
+/// exclusive reservations cannot be made over memory that is already mapped either.
+pub(crate) fn reserve(guest_addr: u64, size: usize, exclusive: bool) -> Result<Reservation> {
+    let end = guest_addr
+        .checked_add(size as u64)
+        .ok_or(HypervisorError::BadArgument)?;
+    if size == 0 {
+        return Err(HypervisorError::BadArgument);
+    }
+    let mut reservations = RESERVATIONS.lock().unwrap();
+    if reservations
+        .iter()
+        .any(|r| guest_addr < r.range.end && r.range.start < end)
+    {
+        return Err(HypervisorError::Busy);
+    }
+    if exclusive
+        && MAPPED_RANGES
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|r| guest_addr < r.end && r.start < end)
+    {
+        return Err(HypervisorError::Busy);
+    }
+    reservations.push(ReservedRange {
+        range: guest_addr..end,
+        exclusive,
+    });
+    Ok(Reservation {
+        range: guest_addr..end,
+    })
+}
+
+/// Represents a guest address range reservation, which is released when dropped.
+///
+/// Instances are created using [`VirtualMachine::reserve_range`].
+#[derive(Debug, Hash, Eq, PartialEq)]
+pub struct Reservation {
+    range: core::ops::Range<u64>,
+}
+
+impl Reservation {
+    /// Retrieves the reservation's guest address.
+    pub fn get_guest_addr(&self) -> u64 {
+        self.range.start
+    }
+
+    /// Retrieves the reservation's size.
+    pub fn get_size(&self) -> usize {
+        (self.range.end - self.range.start) as usize
+    }
+}
+
+impl std::ops::Drop for Reservation {
+    fn drop(&mut self) {
+        RESERVATIONS
+            .lock()
+            .unwrap()
+            .retain(|r| r.range != self.range);
+    }
+}
+
 unsafe impl Sync for VirtualMachine {}
 
 /// Represents the unique virtual machine instance of the current process.
@@ -799,11 +1055,291 @@ pub struct VirtualMachine {
 
 impl VirtualMachine {
     /// Creates a new virtual machine instance for the current process.
+    ///
+    /// Returns [`HypervisorError::Denied`] if the binary was not granted the hypervisor
+    /// entitlement.
     pub fn new() -> Result<Self> {
         let config = ptr::null_mut();
         hv_unsafe_call!(hv_vm_create(config))?;
+        VM_ALIVE.store(true, core::sync::atomic::Ordering::SeqCst);
+        NEXT_GUEST_ADDR.store(FIRST_GUEST_ADDR, core::sync::atomic::Ordering::SeqCst);
         Ok(Self { config })
     }
+
+    /// Returns the [`VcpuInstance`]s of all vCPUs that are currently alive in the virtual
+    /// machine, regardless of the thread that owns them.
+    pub fn live_vcpus(&self) -> Vec<VcpuInstance> {
+        LIVE_VCPUS
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|&id| VcpuInstance(id))
+            .collect()
+    }
+
+    /// Maps the host memory range starting at `host_addr` at address `guest_addr` in the guest,
+    /// without copying it into a new allocation.
+    ///
+    /// Returns [`HypervisorError::Unaligned`] if `host_addr`, `size` or `guest_addr` are not
+    /// aligned on [`PAGE_SIZE`], and [`HypervisorError::BadArgument`] if `perms` is
+    /// [`MemPerms::None`].
+    ///
+    /// # Safety
+    ///
+    /// The memory range must be valid for reads and writes for `size` bytes and must outlive the
+    /// returned [`BorrowedMapping`], since the guest accesses it directly. The host memory is not
+    /// freed when the mapping is dropped.
+    pub unsafe fn map_host(
+        &self,
+        host_addr: *mut u8,
+        size: usize,
+        guest_addr: u64,
+        perms: MemPerms,
+    ) -> Result<BorrowedMapping> {
+        Self::map_host_inner(host_addr, size, guest_addr, perms)
+    }
+
+    /// Implementation of [`VirtualMachine::map_host`], which does not require a virtual machine
+    /// reference.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as [`VirtualMachine::map_host`].
+    unsafe fn map_host_inner(
+        host_addr: *mut u8,
+        size: usize,
+        guest_addr: u64,
+        perms: MemPerms,
+    ) -> Result<BorrowedMapping> {
+        if !(host_addr as usize).is_multiple_of(PAGE_SIZE)
+            || !size.is_multiple_of(PAGE_SIZE)
+            || !guest_addr.is_multiple_of(PAGE_SIZE as u64)
+        {
+            return Err(HypervisorError::Unaligned);
+        }
+        if perms == MemPerms::None {
+            return Err(HypervisorError::BadArgument);
+        }
+        let _reservations = check_reservations(guest_addr, size)?;
+        let host_addr = host_addr as *const c_void;
+        hv_unsafe_call!(hv_vm_map(
+            host_addr,
+            guest_addr,
+            size,
+            Into::<hv_memory_flags_t>::into(perms)
+        ))?;
+        record_mapped_range(guest_addr, size);
+        Ok(BorrowedMapping {
+            host_addr,
+            guest_addr,
+            size,
+            perms,
+        })
+    }
+
+    /// Creates an exception vector table at address `vbar` in the guest, where each of the 16
+    /// vectors branches to `handler`.
+    ///
+    /// The vector table is mapped with read and execute permissions. VBAR_EL1 still needs to be
+    /// set to `vbar` on the vCPUs that should use it.
+    ///
+    /// Returns [`HypervisorError::BadArgument`] if `handler` is not 4-byte aligned or out of
+    /// range of a `b` instruction from the vector table, or if the vector table would overflow
+    /// the guest address space.
+    pub fn create_vector_table(&self, vbar: u64, handler: u64) -> Result<Mapping> {
+        let mut table = vec![0; VECTOR_TABLE_SIZE as usize];
+        for (i, vector) in table.chunks_exact_mut(VECTOR_SIZE as usize).enumerate() {
+            let pc = vbar
+                .checked_add(i as u64 * VECTOR_SIZE)
+                .ok_or(HypervisorError::BadArgument)?;
+            let insn = encoding::encode_b(pc, handler)?;
+            vector[..4].copy_from_slice(&insn.to_le_bytes());
+        }
+        let mut mem = Mapping::new(PAGE_SIZE)?;
+        mem.load_from_slice(&table)?;
+        mem.map(vbar, MemPerms::RX)?;
+        Ok(mem)
+    }
+
+    /// Creates a new mapping of `size` bytes and maps it at address `guest_addr` with
+    /// permissions `perms`.
+    ///
+    /// This is equivalent to calling [`Mappable::new`] and [`Mappable::map`], and the host
+    /// allocation is released if the mapping cannot be mapped.
+    pub fn create_mapping(&self, guest_addr: u64, size: usize, perms: MemPerms) -> Result<Mapping> {
+        let mut mem = Mapping::new(size)?;
+        mem.map(guest_addr, perms)?;
+        Ok(mem)
+    }
+
+    /// Creates a guest stack of `size` bytes at address `guest_base` and returns its mapping
+    /// along with the initial stack pointer.
+    ///
+    /// AArch64 stacks grow downwards, so the initial stack pointer is the 16-byte aligned top of
+    /// the region, i.e. `guest_base` plus the mapping size. The stack is mapped with read and
+    /// write permissions.
+    ///
+    /// Returns [`HypervisorError::BadArgument`] if the stack would overflow the guest address
+    /// space.
+    pub fn create_stack(&self, guest_base: u64, size: usize) -> Result<(Mapping, u64)> {
+        let mut mem = Mapping::new(size)?;
+        let sp = guest_base
+            .checked_add(mem.get_size() as u64)
+            .ok_or(HypervisorError::BadArgument)?
+            & !0xf;
+        mem.map(guest_base, MemPerms::RW)?;
+        Ok((mem, sp))
+    }
+
+    /// Loads the content of the file at `path` in a new mapping, mapped at address `guest_addr`
+    /// with permissions `perms`, e.g. to load a kernel image.
+    ///
+    /// The mapping size is the file size rounded up to the next multiple of [`PAGE_SIZE`].
+    /// Errors while reading the file are returned as [`loader::LoadError::Io`], and hypervisor
+    /// errors as [`loader::LoadError::Hypervisor`], e.g. [`HypervisorError::BadArgument`] for
+    /// empty files.
+    pub fn load_file(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        guest_addr: u64,
+        perms: MemPerms,
+    ) -> core::result::Result<Mapping, loader::LoadError> {
+        let data = std::fs::read(path)?;
+        let mut mem = Mapping::new(data.len())?;
+        mem.load_from_slice(&data)?;
+        mem.map(guest_addr, perms)?;
+        Ok(mem)
+    }
+
+    /// Runs `count` vCPUs concurrently and returns their exit information, in index order.
+    ///
+    /// Each vCPU is created in its own thread and configured by calling `setup` with the vCPU
+    /// and its index. Once all of them are configured, they start running at the same time.
+    ///
+    /// **Note:** this function returns once all vCPUs have exited. If one of them fails, the
+    /// others still run and the first error is returned. If `setup` panics, the other vCPUs
+    /// still run and the panic is propagated once they have exited.
+    pub fn run_smp<F>(&self, count: u32, setup: F) -> Result<Vec<VcpuExit>>
+    where
+        F: Fn(&Vcpu, usize) -> Result<()> + Sync,
+    {
+        let barrier = Arc::new(std::sync::Barrier::new(count as usize));
+        std::thread::scope(|scope| {
+            let threads = (0..count as usize)
+                .map(|i| {
+                    // Threads wait on the barrier even if an error occurred or if `setup`
+                    // panicked, otherwise the other ones would be blocked.
+                    let mut barrier = sched::StepBarrier::new(barrier.clone());
+                    let setup = &setup;
+                    scope.spawn(move || {
+                        let vcpu = Vcpu::new().and_then(|vcpu| setup(&vcpu, i).map(|_| vcpu));
+                        barrier.wait();
+                        let vcpu = vcpu?;
+                        vcpu.run()?;
+                        Ok(vcpu.get_exit_info())
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|t| t.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect()
+        })
+    }
+
+    /// Returns a human-readable report of the state of `vcpu` and of the memory `mappings`,
+    /// e.g. to attach to bug reports.
+    ///
+    /// The report contains the vCPU registers, its decoded exit information and, for each
+    /// mapping, its guest address range, its permissions, its label and its first and last
+    /// bytes.
+    pub fn report(&self, vcpu: &Vcpu, mappings: &[&dyn Mappable]) -> String {
+        use std::fmt::Write;
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut report = String::new();
+        let _ = writeln!(report, "{}", vcpu);
+        let _ = writeln!(report, "Exit: {:?}", vcpu.get_exit_info());
+        let _ = writeln!(report, "Mappings:");
+        for mapping in mappings {
+            let size = mapping.get_size();
+            let _ = match mapping.get_guest_addr() {
+                Some(addr) => write!(
+                    report,
+                    "  {:016x}-{:016x} {}",
+                    addr,
+                    addr + size as u64,
+                    mapping.get_perms()
+                ),
+                None => write!(report, "  unmapped ({:#x} bytes)", size),
+            };
+            if let Some(label) = mapping.get_label() {
+                let _ = write!(report, " [{}]", label);
+            }
+            let data = mapping.to_vec();
+            let len = data.len().min(16);
+            let _ = writeln!(
+                report,
+                " [first: {}] [last: {}]",
+                hex(&data[..len]),
+                hex(&data[data.len() - len..])
+            );
+        }
+        report
+    }
+
+    /// Returns a page-aligned guest address where `size` bytes can be mapped without overlapping
+    /// with the ranges returned by previous calls, e.g. to place mappings without hardcoding
+    /// their addresses.
+    ///
+    /// Addresses are handed out in increasing order from `0x1000_0000`, and the sequence
+    /// restarts whenever a new virtual machine is created. Ranges mapped at hardcoded addresses
+    /// are not taken into account. Returns [`HypervisorError::BadArgument`] if `size` is 0 and
+    /// [`HypervisorError::NoResources`] if the range would exceed the default 36-bit
+    /// intermediate physical address space.
+    pub fn next_guest_addr(&self, size: usize) -> Result<u64> {
+        if size == 0 {
+            return Err(HypervisorError::BadArgument);
+        }
+        let size = (size as u64)
+            .checked_next_multiple_of(PAGE_SIZE as u64)
+            .ok_or(HypervisorError::NoResources)?;
+        NEXT_GUEST_ADDR
+            .fetch_update(
+                core::sync::atomic::Ordering::SeqCst,
+                core::sync::atomic::Ordering::SeqCst,
+                |addr| {
+                    addr.checked_add(size)
+                        .filter(|&end| end <= GUEST_ADDR_LIMIT)
+                },
+            )
+            .map_err(|_| HypervisorError::NoResources)
+    }
+
+    /// Reserves the guest address range of `size` bytes starting at `guest_addr`, without
+    /// allocating any memory for it.
+    ///
+    /// While the returned [`Reservation`] is alive, memory can only be mapped entirely inside
+    /// or entirely outside of the reserved range, and overlapping reservations cannot be made.
+    /// Both cases return [`HypervisorError::Busy`].
+    pub fn reserve_range(&self, guest_addr: u64, size: usize) -> Result<Reservation> {
+        reserve(guest_addr, size, false)
+    }
+
+    /// Forces all vCPUs currently alive in the virtual machine to exit.
+    pub fn exit_all(&self) -> Result<()> {
+        // Keeps the lock held so that the vCPUs cannot be destroyed while they are being stopped.
+        let live_vcpus = LIVE_VCPUS.lock().unwrap();
+        if live_vcpus.is_empty() {
+            return Ok(());
+        }
+        hv_unsafe_call!(hv_vcpus_exit(live_vcpus.as_ptr(), live_vcpus.len() as u32))
+    }
 }
 
 /// Destroys the virtual machine context of the current process.
@@ -812,6 +1348,68 @@ impl VirtualMachine {
 impl core::ops::Drop for VirtualMachine {
     fn drop(&mut self) {
         hv_unsafe_call!(hv_vm_destroy()).expect("Could not properly destroy VM context");
+        VM_ALIVE.store(false, core::sync::atomic::Ordering::SeqCst);
+        // Guest mappings are destroyed along with the virtual machine.
+        MAPPED_RANGES.lock().unwrap().clear();
+    }
+}
+
+/// Represents the hypervisor capabilities of the host.
+///
+/// Capabilities that depend on a specific macOS version are only retrieved when the
+/// corresponding feature is enabled, and are set to `None` otherwise or if the host does not
+/// support them.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct HostCapabilities {
+    /// The maximum number of vCPUs that can be created by the hypervisor.
+    pub max_vcpu_count: u32,
+    /// The maximum intermediate physical address bit length (requires `macos-13-0`).
+    pub max_ipa_size: Option<u32>,
+    /// The default intermediate physical address bit length (requires `macos-13-0`).
+    pub default_ipa_size: Option<u32>,
+    /// Whether EL2 is supported (requires `macos-15-0`).
+    pub el2_supported: Option<bool>,
+    /// The maximum Streaming Vector Length in bytes (requires `macos-15-2`).
+    pub max_svl_bytes: Option<usize>,
+}
+
+impl HostCapabilities {
+    /// Retrieves the hypervisor capabilities of the host.
+    pub fn query() -> Result<Self> {
+        #[cfg_attr(not(feature = "macos-13-0"), allow(unused_mut))]
+        let mut caps = Self {
+            max_vcpu_count: Vcpu::get_max_count()?,
+            max_ipa_size: None,
+            default_ipa_size: None,
+            el2_supported: None,
+            max_svl_bytes: None,
+        };
+        #[cfg(feature = "macos-13-0")]
+        {
+            let mut size = 0;
+            caps.max_ipa_size = hv_unsafe_call!(hv_vm_config_get_max_ipa_size(&mut size))
+                .ok()
+                .map(|_| size);
+            let mut size = 0;
+            caps.default_ipa_size = hv_unsafe_call!(hv_vm_config_get_default_ipa_size(&mut size))
+                .ok()
+                .map(|_| size);
+        }
+        #[cfg(feature = "macos-15-0")]
+        {
+            let mut supported = false;
+            caps.el2_supported = hv_unsafe_call!(hv_vm_config_get_el2_supported(&mut supported))
+                .ok()
+                .map(|_| supported);
+        }
+        #[cfg(feature = "macos-15-2")]
+        {
+            let mut svl = 0;
+            caps.max_svl_bytes = hv_unsafe_call!(hv_sme_config_get_max_svl_bytes(&mut svl))
+                .ok()
+                .map(|_| svl);
+        }
+        Ok(caps)
     }
 }
 
@@ -912,8 +1510,8 @@ impl std::ops::BitOr for MemPerms {
 /// The size of a memory page on Apple Silicon.
 pub const PAGE_SIZE: usize = 0x4000;
 
-/// Represents a host memory allocation.
-#[derive(Clone, Debug, Eq)]
+/// Represents a host memory allocation, which is freed when dropped.
+#[derive(Debug, Eq)]
 pub(crate) struct MemAlloc {
     /// Host address.
     addr: *const c_void,
@@ -924,9 +1522,22 @@ pub(crate) struct MemAlloc {
 }
 
 impl MemAlloc {
-    /// Creates a new memory allocation for the host using [`std::alloc`].
-    pub(crate) fn new(size: usize) -> std::result::Result<Self, alloc::LayoutError> {
-        let layout = alloc::Layout::from_size_align(size, PAGE_SIZE)?.pad_to_align();
+    /// Creates a new memory allocation for the host using [`std::alloc`], aligned on `align`
+    /// bytes.
+    ///
+    /// The allocation size is rounded up to the next multiple of [`PAGE_SIZE`] and the
+    /// allocation is always at least page-aligned. `size` must not be 0 and `align` must be a
+    /// power of two.
+    pub(crate) fn new(size: usize, align: usize) -> Result<Self> {
+        if size == 0 || !align.is_power_of_two() {
+            return Err(HypervisorError::BadArgument);
+        }
+        let size = size
+            .checked_add(PAGE_SIZE - 1)
+            .ok_or(HypervisorError::BadArgument)?
+            & !(PAGE_SIZE - 1);
+        let layout = alloc::Layout::from_size_align(size, align.max(PAGE_SIZE))
+            .map_err(|_| HypervisorError::BadArgument)?;
         let addr = unsafe { alloc::alloc_zeroed(layout) } as *const c_void;
         Ok(MemAlloc {
             addr,
@@ -959,30 +1570,111 @@ impl std::ops::Drop for MemAlloc {
 /// corresponds in the hypervisor guest.
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub struct MappingInner {
-    host_alloc: MemAlloc,
+    host_alloc: Arc<MemAlloc>,
     guest_addr: Option<u64>,
     size: usize,
+    requested_size: usize,
     perms: MemPerms,
+    page_perms: Vec<MemPerms>,
+    label: Option<String>,
 }
 
 /// Represents a memory range exclusive to a single thread.
 ///
 /// **Note:** a memory mapping is available to all vCPU running in a given VM instance, but only
-/// one vCPU-owning thread can access it.
-#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+/// one vCPU-owning thread can access it. The mapping can still be moved to another thread, e.g.
+/// the one that will own the vCPU, which then becomes the only one able to access it.
+///
+/// Mappings are compared, ordered and hashed based on their placement in the guest, i.e. their
+/// guest address and size, rather than on their contents or host allocation. Unmapped mappings
+/// are ordered before mapped ones and, since they have no placement, compare by size only: two
+/// unmapped mappings of the same size are equal, which makes unmapped mappings unsuitable as set
+/// or map keys.
+///
+/// Mappings do not implement [`Clone`], since a copy would share the guest mapping and unmap it a
+/// second time when dropped, possibly after another mapping was mapped at the same address. Use
+/// [`Mappable::deep_clone`] to copy a mapping's contents into a new allocation instead.
+#[derive(Debug)]
 pub struct Mapping {
     inner: MappingInner,
 }
 
+// The host allocation and its guest mapping belong to the process rather than to the thread that
+// created them, so a mapping can be moved to and used from another thread.
+unsafe impl Send for Mapping {}
+
+impl PartialEq for Mapping {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Mapping {}
+
+impl PartialOrd for Mapping {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Mapping {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.inner.guest_addr, self.inner.size).cmp(&(other.inner.guest_addr, other.inner.size))
+    }
+}
+
+impl Hash for Mapping {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.guest_addr.hash(state);
+        self.inner.size.hash(state);
+    }
+}
+
+impl GuestMemory for Mapping {
+    fn protect(&mut self, perms: MemPerms) -> Result<()> {
+        Self::protect_inner(&mut self.inner, perms)
+    }
+
+    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
+        Self::read_inner(&self.inner, guest_addr, data)
+    }
+
+    fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
+        Self::write_inner(&mut self.inner, guest_addr, data)
+    }
+
+    fn get_host_addr(&self) -> *const u8 {
+        self.inner.host_alloc.addr as *const u8
+    }
+
+    fn get_guest_addr(&self) -> Option<u64> {
+        self.inner.guest_addr
+    }
+
+    fn get_size(&self) -> usize {
+        self.inner.size
+    }
+}
+
 impl Mappable for Mapping {
-    fn new(size: usize) -> std::result::Result<Self, alloc::LayoutError> {
-        let host_alloc = MemAlloc::new(size)?;
+    fn new(size: usize) -> Result<Self> {
+        Self::new_aligned(size, PAGE_SIZE)
+    }
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn new_aligned(size: usize, align: usize) -> Result<Self> {
+        let requested_size = size;
+        let host_alloc = Arc::new(MemAlloc::new(size, align)?);
+        let size = host_alloc.size;
         Ok(Self {
             inner: MappingInner {
                 host_alloc,
                 guest_addr: None,
                 size,
+                requested_size,
                 perms: MemPerms::None,
+                page_perms: vec![MemPerms::None; size / PAGE_SIZE],
+                label: None,
             },
         })
     }
@@ -995,28 +1687,53 @@ impl Mappable for Mapping {
         Self::unmap_inner(&mut self.inner)
     }
 
-    fn protect(&mut self, perms: MemPerms) -> Result<()> {
-        Self::protect_inner(&mut self.inner, perms)
+    fn protect_range(&mut self, guest_addr: u64, size: usize, perms: MemPerms) -> Result<()> {
+        Self::protect_range_inner(&mut self.inner, guest_addr, size, perms)
     }
 
-    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
-        Self::read_inner(&self.inner, guest_addr, data)
+    fn get_requested_size(&self) -> usize {
+        self.inner.requested_size
     }
 
-    fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
-        Self::write_inner(&mut self.inner, guest_addr, data)
+    fn get_perms(&self) -> MemPerms {
+        self.inner.perms
     }
 
-    fn get_host_addr(&self) -> *const u8 {
-        self.inner.host_alloc.addr as *const u8
+    fn get_page_perms(&self, guest_addr: u64) -> Result<MemPerms> {
+        Self::page_perms_inner(&self.inner, guest_addr)
     }
 
-    fn get_guest_addr(&self) -> Option<u64> {
-        self.inner.guest_addr
+    fn set_label(&mut self, label: &str) {
+        self.inner.label = Some(label.to_string());
     }
 
-    fn get_size(&self) -> usize {
-        self.inner.size
+    fn get_label(&self) -> Option<String> {
+        self.inner.label.clone()
+    }
+}
+
+impl Mapping {
+    /// Maps the host memory of this mapping at the additional address `guest_addr` with
+    /// permissions `perms`, e.g. to access the same memory through several guest addresses.
+    ///
+    /// The alias is unmapped when the returned [`MemoryAlias`] is dropped. It keeps the host
+    /// memory alive, which means it is only freed once the mapping and all its aliases are
+    /// dropped. Unmapping or protecting the mapping does not affect its aliases.
+    ///
+    /// **Note:** the mapping and its aliases access the same host memory without any
+    /// synchronization. Aliases cannot be moved to another thread, and the mapping must not be
+    /// moved to another thread while it has aliases.
+    pub fn add_alias(&mut self, guest_addr: u64, perms: MemPerms) -> Result<MemoryAlias> {
+        let host_alloc = self.inner.host_alloc.clone();
+        let alias = unsafe {
+            VirtualMachine::map_host_inner(
+                host_alloc.addr as *mut u8,
+                host_alloc.size,
+                guest_addr,
+                perms,
+            )?
+        };
+        Ok(MemoryAlias { alias, host_alloc })
     }
 }
 
@@ -1043,15 +1760,54 @@ impl PartialEq for MappingShared {
     }
 }
 
+impl GuestMemory for MappingShared {
+    fn protect(&mut self, perms: MemPerms) -> Result<()> {
+        let mut inner = self.inner.write().unwrap();
+        Self::protect_inner(&mut inner, perms)
+    }
+
+    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
+        let inner = self.inner.read().unwrap();
+        Self::read_inner(&inner, guest_addr, data)
+    }
+
+    fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.write().unwrap();
+        Self::write_inner(&mut inner, guest_addr, data)
+    }
+
+    fn get_host_addr(&self) -> *const u8 {
+        self.inner.read().unwrap().host_alloc.addr as *const u8
+    }
+
+    fn get_guest_addr(&self) -> Option<u64> {
+        self.inner.read().unwrap().guest_addr
+    }
+
+    fn get_size(&self) -> usize {
+        self.inner.read().unwrap().size
+    }
+}
+
 impl Mappable for MappingShared {
-    fn new(size: usize) -> std::result::Result<Self, alloc::LayoutError> {
-        let host_alloc = MemAlloc::new(size)?;
+    fn new(size: usize) -> Result<Self> {
+        Self::new_aligned(size, PAGE_SIZE)
+    }
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn new_aligned(size: usize, align: usize) -> Result<Self> {
+        let requested_size = size;
+        let host_alloc = Arc::new(MemAlloc::new(size, align)?);
+        let size = host_alloc.size;
         Ok(Self {
             inner: Arc::new(RwLock::new(MappingInner {
                 host_alloc,
                 guest_addr: None,
                 size,
+                requested_size,
                 perms: MemPerms::None,
+                page_perms: vec![MemPerms::None; size / PAGE_SIZE],
+                label: None,
             })),
         })
     }
@@ -1066,31 +1822,30 @@ impl Mappable for MappingShared {
         Self::unmap_inner(&mut inner)
     }
 
-    fn protect(&mut self, perms: MemPerms) -> Result<()> {
+    fn protect_range(&mut self, guest_addr: u64, size: usize, perms: MemPerms) -> Result<()> {
         let mut inner = self.inner.write().unwrap();
-        Self::protect_inner(&mut inner, perms)
+        Self::protect_range_inner(&mut inner, guest_addr, size, perms)
     }
 
-    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
-        let inner = self.inner.read().unwrap();
-        Self::read_inner(&inner, guest_addr, data)
+    fn get_requested_size(&self) -> usize {
+        self.inner.read().unwrap().requested_size
     }
 
-    fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
-        let mut inner = self.inner.write().unwrap();
-        Self::write_inner(&mut inner, guest_addr, data)
+    fn get_perms(&self) -> MemPerms {
+        self.inner.read().unwrap().perms
     }
 
-    fn get_host_addr(&self) -> *const u8 {
-        self.inner.read().unwrap().host_alloc.addr as *const u8
+    fn get_page_perms(&self, guest_addr: u64) -> Result<MemPerms> {
+        let inner = self.inner.read().unwrap();
+        Self::page_perms_inner(&inner, guest_addr)
     }
 
-    fn get_guest_addr(&self) -> Option<u64> {
-        self.inner.read().unwrap().guest_addr
+    fn set_label(&mut self, label: &str) {
+        self.inner.write().unwrap().label = Some(label.to_string());
     }
 
-    fn get_size(&self) -> usize {
-        self.inner.read().unwrap().size
+    fn get_label(&self) -> Option<String> {
+        self.inner.read().unwrap().label.clone()
     }
 }
 
@@ -1107,25 +1862,238 @@ impl std::ops::Drop for MappingShared {
     }
 }
 
-pub trait Mappable {
-    /// Creates a new allocation object.
-    fn new(size: usize) -> std::result::Result<Self, alloc::LayoutError>
-    where
-        Self: Sized;
+/// Represents a memory range owned by the host that is mapped in the guest without being copied.
+///
+/// The memory range is unmapped from the guest, but not freed, when this object is dropped.
+/// Instances are created using [`VirtualMachine::map_host`].
+#[derive(Debug, Hash, Eq, PartialEq)]
+pub struct BorrowedMapping {
+    host_addr: *const c_void,
+    guest_addr: u64,
+    size: usize,
+    perms: MemPerms,
+}
 
-    /// Maps the host allocation in the guest.
-    fn map(&mut self, guest_addr: u64, perms: MemPerms) -> Result<()>;
+impl BorrowedMapping {
+    /// Retrieves the memory mapping's guest address.
+    pub fn get_guest_addr(&self) -> u64 {
+        self.guest_addr
+    }
 
-    /// Maps the host allocation in the guest.
-    fn unmap(&mut self) -> Result<()>;
+    /// Retrieves the memory mapping's permissions in the guest.
+    pub fn get_perms(&self) -> MemPerms {
+        self.perms
+    }
 
-    /// Changes the protections of memory mapping in the guest.
-    fn protect(&mut self, perms: MemPerms) -> Result<()>;
+    /// Returns the host address corresponding to the `size` bytes at address `guest_addr`.
+    fn host_addr_of(&self, guest_addr: u64, size: usize) -> Result<*mut u8> {
+        let end = guest_addr
+            .checked_add(size as u64)
+            .ok_or(HypervisorError::OutOfBounds)?;
+        if guest_addr < self.guest_addr || end > self.guest_addr + self.size as u64 {
+            return Err(HypervisorError::OutOfBounds);
+        }
+        Ok(unsafe { (self.host_addr as *mut u8).add((guest_addr - self.guest_addr) as usize) })
+    }
+}
 
-    /// Reads from a memory mapping in the guest at address `guest_addr`.
-    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize>;
+impl GuestMemory for BorrowedMapping {
+    fn protect(&mut self, perms: MemPerms) -> Result<()> {
+        hv_unsafe_call!(hv_vm_protect(
+            self.guest_addr,
+            self.size,
+            Into::<hv_memory_flags_t>::into(perms)
+        ))?;
+        self.perms = perms;
+        Ok(())
+    }
+
+    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
+        let host_addr = self.host_addr_of(guest_addr, data.len())?;
+        unsafe { ptr::copy(host_addr, data.as_mut_ptr(), data.len()) };
+        Ok(data.len())
+    }
+
+    fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
+        let host_addr = self.host_addr_of(guest_addr, data.len())?;
+        unsafe { ptr::copy(data.as_ptr(), host_addr, data.len()) };
+        Ok(data.len())
+    }
+
+    fn get_host_addr(&self) -> *const u8 {
+        self.host_addr as *const u8
+    }
+
+    fn get_guest_addr(&self) -> Option<u64> {
+        Some(self.guest_addr)
+    }
+
+    fn get_size(&self) -> usize {
+        self.size
+    }
+}
+
+impl std::ops::Drop for BorrowedMapping {
+    fn drop(&mut self) {
+        let _ = hv_unsafe_call!(hv_vm_unmap(self.guest_addr, self.size));
+        forget_mapped_range(self.guest_addr, self.size);
+    }
+}
+
+/// Represents an additional guest address at which the host memory of a [`Mapping`] is mapped.
+///
+/// The alias is unmapped from the guest when this object is dropped, and the host memory is
+/// freed once the mapping and all its aliases are dropped. Instances are created using
+/// [`Mapping::add_alias`].
+///
+/// Aliases are not [`Send`], since accesses through them are not synchronized with the ones
+/// made through the mapping:
+///
+/// ```compile_fail,E0277
+/// use applevisor::*;
+///
+/// let _vm = VirtualMachine::new().unwrap();
+/// let mut mem = Mapping::new(0x1000).unwrap();
+/// mem.map(0x10000, MemPerms::RW).unwrap();
+/// let mut alias = mem.add_alias(0x20000, MemPerms::RW).unwrap();
+/// std::thread::spawn(move || alias.write(0x20000, &[0x41]));
+/// mem.write(0x10000, &[0x42]).unwrap();
+/// ```
+#[derive(Debug, Hash, Eq, PartialEq)]
+pub struct MemoryAlias {
+    // The alias must be unmapped before the host memory it references is released.
+    alias: BorrowedMapping,
+    host_alloc: Arc<MemAlloc>,
+}
+
+impl MemoryAlias {
+    /// Retrieves the alias' permissions in the guest.
+    pub fn get_perms(&self) -> MemPerms {
+        self.alias.get_perms()
+    }
+}
+
+impl GuestMemory for MemoryAlias {
+    fn protect(&mut self, perms: MemPerms) -> Result<()> {
+        self.alias.protect(perms)
+    }
+
+    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
+        self.alias.read(guest_addr, data)
+    }
+
+    fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
+        self.alias.write(guest_addr, data)
+    }
+
+    fn get_host_addr(&self) -> *const u8 {
+        self.host_alloc.addr as *const u8
+    }
+
+    fn get_guest_addr(&self) -> Option<u64> {
+        Some(self.alias.get_guest_addr())
+    }
+
+    fn get_size(&self) -> usize {
+        self.alias.get_size()
+    }
+}
+
+/// Represents a group of memory mappings accessed as a single logical buffer, e.g. to read data
+/// spanning several adjacent mappings.
+///
+/// Accesses are split across the mappings covering them and return
+/// [`HypervisorError::OutOfBounds`], without reading or writing anything, if part of the range
+/// is not covered by any of the mappings.
+pub struct CompositeMemory<'a> {
+    parts: Vec<&'a mut dyn GuestMemory>,
+}
+
+impl<'a> CompositeMemory<'a> {
+    /// Groups the memory mappings in `parts`, which are ordered by guest address.
+    ///
+    /// Returns [`HypervisorError::NotMapped`] if one of the mappings is not mapped.
+    pub fn new(mut parts: Vec<&'a mut dyn GuestMemory>) -> Result<Self> {
+        if parts.iter().any(|part| part.get_guest_addr().is_none()) {
+            return Err(HypervisorError::NotMapped);
+        }
+        parts.sort_by_key(|part| part.get_guest_addr());
+        Ok(Self { parts })
+    }
+
+    /// Splits the access of `len` bytes at address `guest_addr` into `(part, guest_addr, len)`
+    /// accesses to the underlying mappings.
+    fn split(&self, guest_addr: u64, len: usize) -> Result<Vec<(usize, u64, usize)>> {
+        let end = guest_addr
+            .checked_add(len as u64)
+            .ok_or(HypervisorError::OutOfBounds)?;
+        let mut accesses = vec![];
+        let mut addr = guest_addr;
+        while addr < end {
+            let (index, range) = self
+                .parts
+                .iter()
+                .enumerate()
+                .filter_map(|(i, part)| part.guest_range().map(|range| (i, range)))
+                .find(|(_, range)| range.contains(&addr))
+                .ok_or(HypervisorError::OutOfBounds)?;
+            let size = (end.min(range.end) - addr) as usize;
+            accesses.push((index, addr, size));
+            addr += size as u64;
+        }
+        Ok(accesses)
+    }
+
+    /// Reads from the grouped mappings at address `guest_addr`.
+    pub fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
+        let mut offset = 0;
+        for (index, addr, size) in self.split(guest_addr, data.len())? {
+            self.parts[index].read(addr, &mut data[offset..offset + size])?;
+            offset += size;
+        }
+        Ok(data.len())
+    }
+
+    /// Writes to the grouped mappings at address `guest_addr`.
+    pub fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
+        let mut offset = 0;
+        for (index, addr, size) in self.split(guest_addr, data.len())? {
+            self.parts[index].write(addr, &data[offset..offset + size])?;
+            offset += size;
+        }
+        Ok(data.len())
+    }
+}
+
+/// Operations common to all memory ranges mapped in the guest, regardless of how their host
+/// memory is managed.
+///
+/// Unlike [`Mappable`], this trait can be used as a trait object, e.g. to access both
+/// [`Mapping`]s and [`BorrowedMapping`]s through a `&dyn GuestMemory`.
+///
+/// **Note:** [`GuestMemory::read`], [`GuestMemory::write`], [`GuestMemory::protect`],
+/// [`GuestMemory::get_host_addr`], [`GuestMemory::get_guest_addr`] and
+/// [`GuestMemory::get_size`] used to be methods of [`Mappable`], which is now a subtrait of
+/// `GuestMemory`. Code importing `Mappable` alone must also import `GuestMemory` (or use
+/// `applevisor::*`) to keep calling them.
+///
+/// [`Mappable::map`] and [`Mappable::unmap`] are not part of this trait because
+/// [`BorrowedMapping`]s and [`MemoryAlias`]es are mapped when they are created and unmapped when
+/// they are dropped, so they cannot implement them.
+pub trait GuestMemory {
+    /// Changes the protections of memory mapping in the guest.
+    fn protect(&mut self, perms: MemPerms) -> Result<()>;
+
+    /// Reads from a memory mapping in the guest at address `guest_addr`.
+    ///
+    /// Returns [`HypervisorError::NotMapped`] if the mapping is not mapped and
+    /// [`HypervisorError::OutOfBounds`] if the range read is not entirely within the mapping.
+    fn read(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize>;
 
     /// Writes to a memory mapping in the guest at address `guest_addr`.
+    ///
+    /// Returns [`HypervisorError::NotMapped`] if the mapping is not mapped and
+    /// [`HypervisorError::OutOfBounds`] if the range written is not entirely within the mapping.
     fn write(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize>;
 
     /// Retrieves the memory mapping's host address.
@@ -1134,9 +2102,144 @@ pub trait Mappable {
     /// Retrieves the memory mapping's guest address.
     fn get_guest_addr(&self) -> Option<u64>;
 
-    /// Retrieves the memory mapping's size.
+    /// Retrieves the memory mapping's size, rounded up to a multiple of [`PAGE_SIZE`].
     fn get_size(&self) -> usize;
 
+    /// Returns whether a virtual machine currently exists in the process.
+    ///
+    /// Memory can outlive the virtual machine of the process, but operating on it once the
+    /// virtual machine has been destroyed is a logic error: the guest mappings no longer exist
+    /// and only the host allocation can still be accessed.
+    ///
+    /// **Note:** the memory does not record which virtual machine it was mapped in. If the
+    /// virtual machine is destroyed and a new one is created, this returns `true` even though the
+    /// memory's guest mapping was lost along with the first virtual machine.
+    fn is_vm_alive(&self) -> bool {
+        VM_ALIVE.load(core::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Retrieves the range of guest addresses covered by the memory mapping, or `None` if it is
+    /// not mapped.
+    fn guest_range(&self) -> Option<core::ops::Range<u64>> {
+        let guest_addr = self.get_guest_addr()?;
+        Some(guest_addr..guest_addr + self.get_size() as u64)
+    }
+
+    /// Returns whether `guest_addr` is covered by the memory mapping.
+    ///
+    /// Unmapped memory does not contain any address.
+    fn contains(&self, guest_addr: u64) -> bool {
+        self.guest_range()
+            .is_some_and(|range| range.contains(&guest_addr))
+    }
+
+    /// Returns whether the `other_size` bytes starting at `other_addr` overlap with the memory
+    /// mapping.
+    ///
+    /// Unmapped memory does not overlap with anything, and neither do empty ranges.
+    fn overlaps(&self, other_addr: u64, other_size: usize) -> bool {
+        let other_end = other_addr.saturating_add(other_size as u64);
+        self.guest_range()
+            .is_some_and(|range| other_addr < range.end && range.start < other_end)
+    }
+}
+
+pub trait Mappable: GuestMemory {
+    /// Creates a new allocation object.
+    ///
+    /// `size` must be at least one byte and is rounded up to the next multiple of
+    /// [`PAGE_SIZE`]. Returns [`HypervisorError::BadArgument`] if `size` is 0.
+    fn new(size: usize) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Creates a new allocation object whose host allocation is aligned on `align` bytes.
+    ///
+    /// `size` is rounded up to the next multiple of [`PAGE_SIZE`] and the host allocation is
+    /// always at least page-aligned. Returns [`HypervisorError::BadArgument`] if `size` is 0 or
+    /// if `align` is not a power of two.
+    fn new_aligned(size: usize, align: usize) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Creates a new allocation object, without rounding `size` up.
+    ///
+    /// Returns [`HypervisorError::BadArgument`] if `size` is 0 or is not a multiple of
+    /// [`PAGE_SIZE`].
+    fn new_exact(size: usize) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        if !size.is_multiple_of(PAGE_SIZE) {
+            return Err(HypervisorError::BadArgument);
+        }
+        Self::new(size)
+    }
+
+    /// Maps the host allocation in the guest.
+    ///
+    /// Returns [`HypervisorError::Unaligned`] if `guest_addr` is not aligned on [`PAGE_SIZE`].
+    ///
+    /// Returns [`HypervisorError::BadArgument`] if `perms` is [`MemPerms::None`], since mapping
+    /// memory that the guest cannot access is almost always a mistake. Use
+    /// [`GuestMemory::protect`] on a mapped range instead to make it inaccessible.
+    ///
+    /// Address 0 is a valid guest address: whether the allocation is mapped is tracked
+    /// separately, as reported by [`GuestMemory::get_guest_addr`].
+    fn map(&mut self, guest_addr: u64, perms: MemPerms) -> Result<()>;
+
+    /// Unmaps the host allocation from the guest.
+    ///
+    /// Returns [`HypervisorError::NotMapped`] if the allocation is not currently mapped.
+    fn unmap(&mut self) -> Result<()>;
+
+    /// Changes the protections of the `size` bytes of the memory mapping starting at
+    /// `guest_addr`, leaving the rest of the mapping untouched.
+    ///
+    /// Returns [`HypervisorError::Unaligned`] if the range is not aligned on [`PAGE_SIZE`] and
+    /// [`HypervisorError::OutOfBounds`] if it does not lie within the mapping.
+    fn protect_range(&mut self, guest_addr: u64, size: usize, perms: MemPerms) -> Result<()>;
+
+    /// Retrieves the size that was originally requested when the memory mapping was created.
+    fn get_requested_size(&self) -> usize;
+
+    /// Retrieves the memory mapping's permissions in the guest, as set by [`Mappable::map`] or
+    /// [`GuestMemory::protect`].
+    ///
+    /// Permissions changed on part of the mapping using [`Mappable::protect_range`] are only
+    /// reflected here if the range covers the whole mapping. Use [`Mappable::get_page_perms`] to
+    /// retrieve the permissions of a given page.
+    fn get_perms(&self) -> MemPerms;
+
+    /// Retrieves the permissions in the guest of the page containing `guest_addr`.
+    fn get_page_perms(&self, guest_addr: u64) -> Result<MemPerms>;
+
+    /// Retrieves the permissions in the guest of the `page_index`-th page of the memory mapping.
+    ///
+    /// Returns `None` if the mapping is not mapped or if it has fewer than `page_index + 1`
+    /// pages.
+    fn get_page_perms_by_index(&self, page_index: usize) -> Option<MemPerms> {
+        let offset = page_index.checked_mul(PAGE_SIZE)?;
+        let guest_addr = self.get_guest_addr()?.checked_add(offset as u64)?;
+        self.get_page_perms(guest_addr).ok()
+    }
+
+    /// Attaches a `label` to the memory mapping, e.g. `"stack"` or `"code"`, which is shown in
+    /// diagnostic outputs such as [`Mappable::hexdump`] and [`VirtualMachine::report`].
+    fn set_label(&mut self, label: &str);
+
+    /// Retrieves the label of the memory mapping, if one was set.
+    fn get_label(&self) -> Option<String>;
+
+    /// Consumes the memory mapping and returns it with `label` attached to it.
+    fn with_label(mut self, label: &str) -> Self
+    where
+        Self: Sized,
+    {
+        self.set_label(label);
+        self
+    }
+
     /// Underlying memory mapping function.
     fn map_inner(inner: &mut MappingInner, guest_addr: u64, perms: MemPerms) -> Result<()>
     where
@@ -1146,6 +2249,17 @@ pub trait Mappable {
         if inner.guest_addr.is_some() {
             return Err(HypervisorError::Busy);
         }
+        // Returns if the guest address is not page-aligned.
+        if !guest_addr.is_multiple_of(PAGE_SIZE as u64) {
+            return Err(HypervisorError::Unaligned);
+        }
+        // Returns if the mapping would be inaccessible to the guest.
+        if perms == MemPerms::None {
+            return Err(HypervisorError::BadArgument);
+        }
+        // Returns if the mapping straddles a reserved range, and prevents new reservations until
+        // the mapping is mapped.
+        let _reservations = check_reservations(guest_addr, inner.host_alloc.size)?;
         // Maps the mapping in the guest.
         hv_unsafe_call!(hv_vm_map(
             inner.host_alloc.addr,
@@ -1153,9 +2267,11 @@ pub trait Mappable {
             inner.host_alloc.size,
             Into::<hv_memory_flags_t>::into(perms)
         ))?;
+        record_mapped_range(guest_addr, inner.host_alloc.size);
         // Updates the inner mapping.
         inner.guest_addr = Some(guest_addr);
         inner.perms = perms;
+        inner.page_perms.fill(perms);
         Ok(())
     }
 
@@ -1165,9 +2281,10 @@ pub trait Mappable {
         Self: Sized,
     {
         // Returns if the mapping is not mapped.
-        let guest_addr = inner.guest_addr.ok_or(HypervisorError::Error)?;
+        let guest_addr = inner.guest_addr.ok_or(HypervisorError::NotMapped)?;
         // Unmaps the mapping from the guest.
         hv_unsafe_call!(hv_vm_unmap(guest_addr, inner.host_alloc.size))?;
+        forget_mapped_range(guest_addr, inner.host_alloc.size);
         // Updates the inner mapping.
         inner.guest_addr = None;
         Ok(())
@@ -1179,7 +2296,7 @@ pub trait Mappable {
         Self: Sized,
     {
         // Returns if the mapping is not mapped.
-        let guest_addr = inner.guest_addr.ok_or(HypervisorError::Error)?;
+        let guest_addr = inner.guest_addr.ok_or(HypervisorError::NotMapped)?;
         // Changes the guest mapping's protections.
         hv_unsafe_call!(hv_vm_protect(
             guest_addr,
@@ -1188,25 +2305,77 @@ pub trait Mappable {
         ))?;
         // Updates the inner mapping.
         inner.perms = perms;
+        inner.page_perms.fill(perms);
+        Ok(())
+    }
+
+    /// Underlying memory range protection function.
+    fn protect_range_inner(
+        inner: &mut MappingInner,
+        guest_addr: u64,
+        size: usize,
+        perms: MemPerms,
+    ) -> Result<()>
+    where
+        Self: Sized,
+    {
+        // Returns if the mapping is not mapped.
+        let inner_guest_addr = inner.guest_addr.ok_or(HypervisorError::NotMapped)?;
+        // Checks the range is page-aligned and in the guest memory range.
+        if !guest_addr.is_multiple_of(PAGE_SIZE as u64) || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(HypervisorError::Unaligned);
+        }
+        if guest_addr < inner_guest_addr
+            || guest_addr
+                .checked_add(size as u64)
+                .ok_or(HypervisorError::OutOfBounds)?
+                > inner_guest_addr + inner.host_alloc.size as u64
+        {
+            return Err(HypervisorError::OutOfBounds);
+        }
+        // Changes the protections of the guest mapping's pages.
+        hv_unsafe_call!(hv_vm_protect(
+            guest_addr,
+            size,
+            Into::<hv_memory_flags_t>::into(perms)
+        ))?;
+        // Updates the inner mapping.
+        let first = ((guest_addr - inner_guest_addr) as usize) / PAGE_SIZE;
+        inner.page_perms[first..first + size / PAGE_SIZE].fill(perms);
+        if size == inner.host_alloc.size {
+            inner.perms = perms;
+        }
         Ok(())
     }
 
+    /// Underlying page permissions retrieval function.
+    fn page_perms_inner(inner: &MappingInner, guest_addr: u64) -> Result<MemPerms>
+    where
+        Self: Sized,
+    {
+        // Returns if the mapping is not mapped.
+        let inner_guest_addr = inner.guest_addr.ok_or(HypervisorError::NotMapped)?;
+        guest_addr
+            .checked_sub(inner_guest_addr)
+            .and_then(|offset| inner.page_perms.get(offset as usize / PAGE_SIZE))
+            .copied()
+            .ok_or(HypervisorError::OutOfBounds)
+    }
+
     /// Underlying memory read function.
     fn read_inner(inner: &MappingInner, guest_addr: u64, data: &mut [u8]) -> Result<usize>
     where
         Self: Sized,
     {
         // Returns if the mapping is not mapped.
-        let inner_guest_addr = inner.guest_addr.ok_or(HypervisorError::Error)?;
+        let inner_guest_addr = inner.guest_addr.ok_or(HypervisorError::NotMapped)?;
         // Checks the guest addr provided is in the guest memory range.
         let size = data.len();
-        if guest_addr < inner_guest_addr
-            || guest_addr.checked_add(size as u64).unwrap()
-                > inner_guest_addr
-                    .checked_add(inner.host_alloc.size as u64)
-                    .unwrap()
-        {
-            return Err(HypervisorError::BadArgument);
+        let end = guest_addr
+            .checked_add(size as u64)
+            .ok_or(HypervisorError::OutOfBounds)?;
+        if guest_addr < inner_guest_addr || end > inner_guest_addr + inner.host_alloc.size as u64 {
+            return Err(HypervisorError::OutOfBounds);
         }
         // Computes the corresponding host address.
         let offset = guest_addr - inner_guest_addr;
@@ -1222,6 +2391,21 @@ pub trait Mappable {
         Ok(size)
     }
 
+    /// Reads as many bytes as possible from a memory mapping in the guest at address
+    /// `guest_addr`, stopping at the end of the mapping.
+    ///
+    /// Unlike [`GuestMemory::read`], this function does not fail if the range read goes past the
+    /// end of the mapping and returns the number of bytes actually copied, which is 0 if
+    /// `guest_addr` is outside of the mapping.
+    fn read_partial(&self, guest_addr: u64, data: &mut [u8]) -> Result<usize> {
+        let range = self.guest_range().ok_or(HypervisorError::NotMapped)?;
+        if !range.contains(&guest_addr) {
+            return Ok(0);
+        }
+        let size = data.len().min((range.end - guest_addr) as usize);
+        self.read(guest_addr, &mut data[..size])
+    }
+
     /// Reads one byte at address `guest_addr`.
     #[inline]
     fn read_byte(&self, guest_addr: u64) -> Result<u8> {
@@ -1230,7 +2414,7 @@ pub trait Mappable {
         Ok(data[0])
     }
 
-    /// Reads one word at address `guest_addr`.
+    /// Reads one little-endian word at address `guest_addr`.
     #[inline]
     fn read_word(&self, guest_addr: u64) -> Result<u16> {
         let mut data = [0; 2];
@@ -1238,7 +2422,15 @@ pub trait Mappable {
         Ok(u16::from_le_bytes(data[..2].try_into().unwrap()))
     }
 
-    /// Reads one dword at address `guest_addr`.
+    /// Reads one big-endian word at address `guest_addr`.
+    #[inline]
+    fn read_word_be(&self, guest_addr: u64) -> Result<u16> {
+        let mut data = [0; 2];
+        assert_eq!(self.read(guest_addr, &mut data)?, 2);
+        Ok(u16::from_be_bytes(data[..2].try_into().unwrap()))
+    }
+
+    /// Reads one little-endian dword at address `guest_addr`.
     #[inline]
     fn read_dword(&self, guest_addr: u64) -> Result<u32> {
         let mut data = [0; 4];
@@ -1246,7 +2438,15 @@ pub trait Mappable {
         Ok(u32::from_le_bytes(data[..4].try_into().unwrap()))
     }
 
-    /// Reads one qword at address `guest_addr`.
+    /// Reads one big-endian dword at address `guest_addr`.
+    #[inline]
+    fn read_dword_be(&self, guest_addr: u64) -> Result<u32> {
+        let mut data = [0; 4];
+        assert_eq!(self.read(guest_addr, &mut data)?, 4);
+        Ok(u32::from_be_bytes(data[..4].try_into().unwrap()))
+    }
+
+    /// Reads one little-endian qword at address `guest_addr`.
     #[inline]
     fn read_qword(&self, guest_addr: u64) -> Result<u64> {
         let mut data = [0; 8];
@@ -1254,6 +2454,14 @@ pub trait Mappable {
         Ok(u64::from_le_bytes(data[..8].try_into().unwrap()))
     }
 
+    /// Reads one big-endian qword at address `guest_addr`.
+    #[inline]
+    fn read_qword_be(&self, guest_addr: u64) -> Result<u64> {
+        let mut data = [0; 8];
+        assert_eq!(self.read(guest_addr, &mut data)?, 8);
+        Ok(u64::from_be_bytes(data[..8].try_into().unwrap()))
+    }
+
     /// Underlying memory write function.
     fn write_inner(inner: &mut MappingInner, guest_addr: u64, data: &[u8]) -> Result<usize>
     where
@@ -1261,15 +2469,13 @@ pub trait Mappable {
     {
         let size = data.len();
         // Returns if the mapping is not mapped.
-        let inner_guest_addr = inner.guest_addr.ok_or(HypervisorError::Error)?;
+        let inner_guest_addr = inner.guest_addr.ok_or(HypervisorError::NotMapped)?;
         // Checks the guest addr provided is in the guest memory range.
-        if guest_addr < inner_guest_addr
-            || guest_addr.checked_add(size as u64).unwrap()
-                > inner_guest_addr
-                    .checked_add(inner.host_alloc.size as u64)
-                    .unwrap()
-        {
-            return Err(HypervisorError::BadArgument);
+        let end = guest_addr
+            .checked_add(size as u64)
+            .ok_or(HypervisorError::OutOfBounds)?;
+        if guest_addr < inner_guest_addr || end > inner_guest_addr + inner.host_alloc.size as u64 {
+            return Err(HypervisorError::OutOfBounds);
         }
         // Computes the corresponding host address.
         let offset = guest_addr - inner_guest_addr;
@@ -1285,29 +2491,270 @@ pub trait Mappable {
         Ok(size)
     }
 
+    /// Writes to a memory mapping in the guest at address `guest_addr`, only if the guest has
+    /// write access to it.
+    ///
+    /// Unlike [`GuestMemory::write`], which writes to the host allocation regardless of the guest
+    /// permissions, this function returns [`HypervisorError::Denied`] if one of the pages
+    /// written to is not writable by the guest.
+    fn write_checked(&mut self, guest_addr: u64, data: &[u8]) -> Result<usize> {
+        let end = guest_addr
+            .checked_add(data.len() as u64)
+            .ok_or(HypervisorError::OutOfBounds)?;
+        let first_page = guest_addr & !(PAGE_SIZE as u64 - 1);
+        for page in (first_page..end).step_by(PAGE_SIZE) {
+            let perms = self.get_page_perms(page)?;
+            if Into::<hv_memory_flags_t>::into(perms) & HV_MEMORY_WRITE == 0 {
+                return Err(HypervisorError::Denied);
+            }
+        }
+        self.write(guest_addr, data)
+    }
+
+    /// Writes each `(guest_addr, data)` patch of `patches` to the memory mapping.
+    ///
+    /// All patches are validated before any of them is applied, so that memory is left untouched
+    /// if one of them does not lie within the mapping.
+    fn write_scattered(&mut self, patches: &[(u64, &[u8])]) -> Result<()> {
+        let range = self.guest_range().ok_or(HypervisorError::NotMapped)?;
+        for &(guest_addr, data) in patches {
+            let end = guest_addr
+                .checked_add(data.len() as u64)
+                .ok_or(HypervisorError::OutOfBounds)?;
+            if guest_addr < range.start || end > range.end {
+                return Err(HypervisorError::OutOfBounds);
+            }
+        }
+        for &(guest_addr, data) in patches {
+            self.write(guest_addr, data)?;
+        }
+        Ok(())
+    }
+
     /// Writes one byte at address `guest_addr`.
     #[inline]
     fn write_byte(&mut self, guest_addr: u64, data: u8) -> Result<usize> {
         self.write(guest_addr, &[data])
     }
 
-    /// Writes one word at address `guest_addr`.
+    /// Writes one little-endian word at address `guest_addr`.
     #[inline]
     fn write_word(&mut self, guest_addr: u64, data: u16) -> Result<usize> {
         self.write(guest_addr, &data.to_le_bytes())
     }
 
-    /// Writes one dword at address `guest_addr`.
+    /// Writes one big-endian word at address `guest_addr`.
+    #[inline]
+    fn write_word_be(&mut self, guest_addr: u64, data: u16) -> Result<usize> {
+        self.write(guest_addr, &data.to_be_bytes())
+    }
+
+    /// Writes one little-endian dword at address `guest_addr`.
     #[inline]
     fn write_dword(&mut self, guest_addr: u64, data: u32) -> Result<usize> {
         self.write(guest_addr, &data.to_le_bytes())
     }
 
-    /// Writes one qword at address `guest_addr`.
+    /// Writes one big-endian dword at address `guest_addr`.
+    #[inline]
+    fn write_dword_be(&mut self, guest_addr: u64, data: u32) -> Result<usize> {
+        self.write(guest_addr, &data.to_be_bytes())
+    }
+
+    /// Writes one little-endian qword at address `guest_addr`.
     #[inline]
     fn write_qword(&mut self, guest_addr: u64, data: u64) -> Result<usize> {
         self.write(guest_addr, &data.to_le_bytes())
     }
+
+    /// Writes one big-endian qword at address `guest_addr`.
+    #[inline]
+    fn write_qword_be(&mut self, guest_addr: u64, data: u64) -> Result<usize> {
+        self.write(guest_addr, &data.to_be_bytes())
+    }
+
+    /// Reads a value of type `T` at address `guest_addr`, e.g. a device descriptor or a
+    /// translation table entry, using its in-memory representation.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be plain old data: any sequence of `size_of::<T>()` bytes must be a valid value
+    /// of `T`, which excludes references, pointers, `bool` and enums, among others.
+    unsafe fn read_struct<T: Copy>(&self, guest_addr: u64) -> Result<T>
+    where
+        Self: Sized,
+    {
+        let mut value = core::mem::MaybeUninit::<T>::zeroed();
+        let data = core::slice::from_raw_parts_mut(
+            value.as_mut_ptr() as *mut u8,
+            core::mem::size_of::<T>(),
+        );
+        self.read(guest_addr, data)?;
+        Ok(value.assume_init())
+    }
+
+    /// Writes `value` at address `guest_addr`, using its in-memory representation.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be plain old data without padding bytes, e.g. a `#[repr(C)]` struct of integers
+    /// whose fields are laid out contiguously, since all of its bytes are copied to the guest.
+    unsafe fn write_struct<T: Copy>(&mut self, guest_addr: u64, value: &T) -> Result<usize>
+    where
+        Self: Sized,
+    {
+        let data =
+            core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>());
+        self.write(guest_addr, data)
+    }
+
+    /// Returns a copy of the whole content of the host allocation backing the memory mapping.
+    fn to_vec(&self) -> Vec<u8> {
+        let mut data = vec![0; self.get_size()];
+        unsafe {
+            ptr::copy(self.get_host_addr(), data.as_mut_ptr(), data.len());
+        };
+        data
+    }
+
+    /// Copies `data` at the beginning of the host allocation backing the memory mapping.
+    ///
+    /// Returns [`HypervisorError::OutOfBounds`] if `data` is larger than the mapping.
+    fn load_from_slice(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > self.get_size() {
+            return Err(HypervisorError::OutOfBounds);
+        }
+        unsafe {
+            ptr::copy(data.as_ptr(), self.get_host_addr() as *mut u8, data.len());
+        };
+        Ok(())
+    }
+
+    /// Creates a new, unmapped, memory mapping of the same size and with the same label, and
+    /// copies the content of the host allocation backing this mapping into it.
+    ///
+    /// The copy is left unmapped since two mappings cannot occupy the same guest addresses. It
+    /// can then be mapped elsewhere, e.g. to fork the state of a guest.
+    fn deep_clone(&self) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut mem = Self::new(self.get_size())?;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.get_host_addr(),
+                mem.get_host_addr() as *mut u8,
+                self.get_size(),
+            );
+        };
+        if let Some(label) = self.get_label() {
+            mem.set_label(&label);
+        }
+        Ok(mem)
+    }
+
+    /// Zeroes the whole host allocation backing the memory mapping.
+    ///
+    /// Returns [`HypervisorError::NotMapped`] if the mapping is not mapped in the guest.
+    fn zero(&mut self) -> Result<()> {
+        if self.get_guest_addr().is_none() {
+            return Err(HypervisorError::NotMapped);
+        }
+        unsafe {
+            ptr::write_bytes(self.get_host_addr() as *mut u8, 0, self.get_size());
+        };
+        Ok(())
+    }
+
+    /// Returns a 64-bit FNV-1a hash of the whole content of the host allocation backing the
+    /// memory mapping.
+    ///
+    /// This is meant for cheap change detection and is not a cryptographic hash.
+    fn checksum(&self) -> u64 {
+        self.checksum_range(0, self.get_size()).unwrap()
+    }
+
+    /// Returns a 64-bit FNV-1a hash of the `len` bytes at offset `offset` in the host allocation
+    /// backing the memory mapping.
+    ///
+    /// Returns [`HypervisorError::OutOfBounds`] if the range is not entirely within the
+    /// mapping.
+    fn checksum_range(&self, offset: usize, len: usize) -> Result<u64> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.get_size() => {}
+            _ => return Err(HypervisorError::OutOfBounds),
+        }
+        let data = unsafe { core::slice::from_raw_parts(self.get_host_addr().add(offset), len) };
+        Ok(data.iter().fold(0xcbf29ce484222325, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        }))
+    }
+
+    /// Returns an `xxd`-style hexdump of the `len` bytes at address `guest_addr`.
+    ///
+    /// Each line contains the guest address, up to 16 bytes in hexadecimal and their ASCII
+    /// representation. If the mapping has a label, the dump starts with a `[label]` header line.
+    fn hexdump(&self, guest_addr: u64, len: usize) -> Result<String> {
+        let mut data = vec![0; len];
+        self.read(guest_addr, &mut data)?;
+        let mut dump = match self.get_label() {
+            Some(label) => format!("[{}]\n", label),
+            None => String::new(),
+        };
+        for (i, line) in data.chunks(16).enumerate() {
+            let hex = line
+                .chunks(2)
+                .map(|pair| {
+                    pair.iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ascii = line
+                .iter()
+                .map(|&b| match b {
+                    0x20..=0x7e => b as char,
+                    _ => '.',
+                })
+                .collect::<String>();
+            dump += &format!(
+                "{:016x}: {:<39}  {}\n",
+                guest_addr + i as u64 * 16,
+                hex,
+                ascii
+            );
+        }
+        Ok(dump)
+    }
+
+    /// Writes the instructions in `code` at address `guest_addr`, making sure the guest executes
+    /// them instead of stale instructions, e.g. when patching code the guest already ran.
+    ///
+    /// The bytes are written using [`GuestMemory::write`], then the instruction cache is
+    /// invalidated for the corresponding host range. The vCPUs executing the code must not be
+    /// running while it is being patched.
+    fn write_code(&mut self, guest_addr: u64, code: &[u8]) -> Result<usize> {
+        let size = self.write(guest_addr, code)?;
+        let offset = guest_addr - self.get_guest_addr().ok_or(HypervisorError::NotMapped)?;
+        unsafe {
+            sys_icache_invalidate(
+                self.get_host_addr().add(offset as usize) as *mut c_void,
+                size,
+            )
+        };
+        Ok(size)
+    }
+
+    /// Writes a `brk #imm` instruction at address `guest_addr`, using `brk #0` if no immediate
+    /// is provided.
+    ///
+    /// The immediate can be retrieved from the exit information using
+    /// [`VcpuExit::breakpoint_immediate`] to identify which breakpoint was hit.
+    #[inline]
+    fn write_breakpoint(&mut self, guest_addr: u64, imm: Option<u16>) -> Result<usize> {
+        self.write_dword(guest_addr, encoding::encode_brk(imm.unwrap_or(0)))
+    }
 }
 
 // -----------------------------------------------------------------------------------------------
@@ -1315,6 +2762,10 @@ pub trait Mappable {
 // -----------------------------------------------------------------------------------------------
 
 /// Represents a vCPU configuration.
+///
+/// The underlying configuration object is never released, which means copies obtained with
+/// [`Clone`] all refer to the same valid object and can be used to create as many vCPUs as
+/// needed.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct VcpuConfig(hv_vcpu_config_t);
 
@@ -1349,14 +2800,56 @@ impl VcpuConfig {
 
     /// Returns the Cache Size ID Register (CCSIDR_EL1) values for the vCPU configuration and
     /// cache type you specify.
+    ///
+    /// This is the value for the first cache level. Use [`VcpuConfig::ccsidr_for`] to retrieve
+    /// the values of the other levels.
     pub fn get_ccsidr_el1_sys_reg_values(&self, cache_type: CacheType) -> Result<u64> {
-        let mut value = 0;
+        self.ccsidr_for(0, cache_type)
+    }
+
+    /// Returns the Cache Size ID Register (CCSIDR_EL1) value for the cache of type `cache_type`
+    /// at level `level`.
+    ///
+    /// The guest selects the cache level described by CCSIDR_EL1 by writing it to CSSELR_EL1.
+    /// Similarly, `level` is the cache level minus one, as encoded in CSSELR_EL1.Level, i.e. 0
+    /// for the L1 cache. Returns [`HypervisorError::BadArgument`] if `level` is greater than 7.
+    pub fn ccsidr_for(&self, level: u8, cache_type: CacheType) -> Result<u64> {
+        // The framework returns the values of all 8 cache levels at once.
+        let mut values = [0; 8];
         hv_unsafe_call!(hv_vcpu_config_get_ccsidr_el1_sys_reg_values(
             self.0,
             Into::<hv_cache_type_t>::into(cache_type),
-            &mut value
+            values.as_mut_ptr()
         ))?;
-        Ok(value)
+        values
+            .get(level as usize)
+            .copied()
+            .ok_or(HypervisorError::BadArgument)
+    }
+}
+
+/// Represents the geometry of a cache, as described by a Cache Size ID Register (CCSIDR_EL1)
+/// value.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct CacheGeometry {
+    /// The size of a cache line, in bytes.
+    pub line_size: usize,
+    /// The associativity of the cache, i.e. its number of ways.
+    pub associativity: u32,
+    /// The number of sets of the cache.
+    pub num_sets: u32,
+}
+
+/// Decodes the Cache Size ID Register (CCSIDR_EL1) value `ccsidr`, as returned by
+/// [`VcpuConfig::ccsidr_for`].
+///
+/// The value is decoded using the layout of hosts that do not implement `FEAT_CCIDX`, which is
+/// the case of Apple Silicon.
+pub fn decode_ccsidr(ccsidr: u64) -> CacheGeometry {
+    CacheGeometry {
+        line_size: 1 << ((ccsidr & 0b111) + 4),
+        associativity: ((ccsidr >> 3) & 0x3ff) as u32 + 1,
+        num_sets: ((ccsidr >> 13) & 0x7fff) as u32 + 1,
     }
 }
 
@@ -1368,10 +2861,61 @@ impl VcpuConfig {
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct VcpuInstance(hv_vcpu_t);
 
-pub type VcpuExitException = hv_vcpu_exit_exception_t;
-
-/// Represents vCPU exit info.
-#[derive(Clone, Eq, PartialEq, Debug)]
+impl VcpuInstance {
+    /// Stops the vCPU, e.g. to cancel its execution from another thread.
+    ///
+    /// Returns [`HypervisorError::NoDevice`] if the vCPU has already been destroyed.
+    pub fn stop(&self) -> Result<()> {
+        // Keeps the lock held so that the vCPU cannot be destroyed while it is being stopped.
+        let live_vcpus = LIVE_VCPUS.lock().unwrap();
+        if !live_vcpus.contains(&self.0) {
+            return Err(HypervisorError::NoDevice);
+        }
+        hv_unsafe_call!(hv_vcpus_exit(&self.0, 1))
+    }
+}
+
+pub type VcpuExitException = hv_vcpu_exit_exception_t;
+
+/// The type of the values of SIMD and floating point registers, which depends on whether the
+/// `simd_nightly` feature is enabled.
+#[cfg(feature = "simd_nightly")]
+pub type SimdFpValue = simd::i8x16;
+
+/// The type of the values of SIMD and floating point registers, which depends on whether the
+/// `simd_nightly` feature is enabled.
+#[cfg(not(feature = "simd_nightly"))]
+pub type SimdFpValue = u128;
+
+/// Converts the little-endian `bytes` into a SIMD and floating point register value.
+pub fn simd_from_bytes(bytes: &[u8; 16]) -> SimdFpValue {
+    #[cfg(feature = "simd_nightly")]
+    let value = simd::i8x16::from_array(bytes.map(|b| b as i8));
+    #[cfg(not(feature = "simd_nightly"))]
+    let value = u128::from_le_bytes(*bytes);
+    value
+}
+
+/// Converts a SIMD and floating point register value into little-endian bytes.
+pub fn simd_to_bytes(value: SimdFpValue) -> [u8; 16] {
+    #[cfg(feature = "simd_nightly")]
+    let bytes = value.to_array().map(|b| b as u8);
+    #[cfg(not(feature = "simd_nightly"))]
+    let bytes = value.to_le_bytes();
+    bytes
+}
+
+/// Represents the kind of wait instruction that caused a vCPU exit.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum WfxKind {
+    /// Wait For Interrupt (`WFI`).
+    Wfi,
+    /// Wait For Event (`WFE`).
+    Wfe,
+}
+
+/// Represents vCPU exit info.
+#[derive(Copy, Clone, Eq, PartialEq)]
 pub struct VcpuExit {
     pub reason: ExitReason,
     pub exception: VcpuExitException,
@@ -1386,6 +2930,146 @@ impl From<hv_vcpu_exit_t> for VcpuExit {
     }
 }
 
+impl VcpuExit {
+    /// Returns the raw exception class of the exit's syndrome, or `None` if the exit was not
+    /// caused by an exception.
+    fn raw_exception_class(&self) -> Option<u64> {
+        match self.reason {
+            ExitReason::EXCEPTION => Some(esr::ec(self.exception.syndrome) as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns the decoded exception class of the exit, or `None` if the exit was not caused by
+    /// an exception.
+    pub fn exception_class(&self) -> Option<esr::ExceptionClass> {
+        self.raw_exception_class()
+            .map(|ec| esr::ExceptionClass::from(ec as u8))
+    }
+
+    /// Returns whether the exit was caused by a `BRK` instruction.
+    pub fn is_breakpoint(&self) -> bool {
+        // Exception class of a `BRK` instruction executed in AArch64 state.
+        self.raw_exception_class() == Some(0x3c)
+    }
+
+    /// Returns whether the exit was caused by a data abort.
+    pub fn is_data_abort(&self) -> bool {
+        // Exception classes of data aborts taken from a lower or the same exception level.
+        matches!(self.raw_exception_class(), Some(0x24) | Some(0x25))
+    }
+
+    /// Returns the faulting guest virtual address and intermediate physical address, as a
+    /// `(virtual_address, physical_address)` pair, if the exit was caused by a data or
+    /// instruction abort.
+    pub fn fault_addresses(&self) -> Option<(u64, u64)> {
+        // Exception classes of instruction and data aborts taken from a lower or the same
+        // exception level.
+        match self.raw_exception_class()? {
+            0x20 | 0x21 | 0x24 | 0x25 => Some((
+                self.exception.virtual_address,
+                self.exception.physical_address,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the exit was caused by an `HVC` instruction.
+    pub fn is_hvc(&self) -> bool {
+        // Exception class of an `HVC` instruction executed in AArch64 state.
+        self.raw_exception_class() == Some(0x16)
+    }
+
+    /// Returns whether the exit was caused by the virtual timer entering the pending state.
+    ///
+    /// When this happens, the host is expected to call [`Vcpu::handle_vtimer`], which masks the
+    /// virtual timer so the guest can run again without exiting immediately, and usually to
+    /// inject an IRQ using [`Vcpu::set_pending_interrupt`]. Once the guest has handled the
+    /// interrupt and re-armed or disabled its timer, the timer can be unmasked with
+    /// [`Vcpu::set_vtimer_mask`].
+    pub fn is_vtimer(&self) -> bool {
+        self.reason == ExitReason::VTIMER_ACTIVATED
+    }
+
+    /// Returns the kind of wait instruction that caused the exit, or `None` if the exit was not
+    /// caused by a `WFI` or `WFE` instruction.
+    pub fn is_wfx(&self) -> Option<WfxKind> {
+        // Exception class of a trapped `WFI` or `WFE` instruction.
+        if self.raw_exception_class() != Some(0x01) {
+            return None;
+        }
+        // The instruction is identified by the TI bit of the instruction specific syndrome.
+        match self.exception.syndrome & 1 {
+            0 => Some(WfxKind::Wfi),
+            _ => Some(WfxKind::Wfe),
+        }
+    }
+
+    /// Returns the immediate of the `BRK` instruction that caused the exit, or `None` if the
+    /// exit was not caused by a breakpoint.
+    pub fn breakpoint_immediate(&self) -> Option<u16> {
+        if !self.is_breakpoint() {
+            return None;
+        }
+        Some((self.exception.syndrome & 0xffff) as u16)
+    }
+
+    /// Returns whether two exits have the same cause.
+    ///
+    /// Unlike the equality operator, only the exit reasons and, for exceptions, the exception
+    /// classes, instruction lengths and instruction specific syndromes, including ISS2, are
+    /// compared. Reserved bits of the syndromes and fault addresses are ignored.
+    pub fn same_cause(&self, other: &VcpuExit) -> bool {
+        // Mask of the EC, IL, ISS and ISS2 fields of the syndrome.
+        const MASK: u64 = esr::EC_MASK | esr::IL_MASK | esr::ISS_MASK | esr::ISS2_MASK;
+        if self.reason != other.reason {
+            return false;
+        }
+        match self.reason {
+            ExitReason::EXCEPTION => {
+                self.exception.syndrome & MASK == other.exception.syndrome & MASK
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns whether the exit was caused by a software step exception.
+    fn is_software_step(&self) -> bool {
+        // Exception class of a software step exception taken from a lower exception level.
+        self.raw_exception_class() == Some(0x32)
+    }
+}
+
+impl std::fmt::Debug for VcpuExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut dbg = f.debug_struct("VcpuExit");
+        dbg.field("reason", &self.reason);
+        if let Some(ec) = self.raw_exception_class() {
+            dbg.field(
+                "syndrome",
+                &format_args!("{:#018x}", self.exception.syndrome),
+            )
+            .field(
+                "exception_class",
+                &format_args!(
+                    "{:#04x} ({})",
+                    ec,
+                    esr::ExceptionClass::from(ec as u8).name()
+                ),
+            )
+            .field(
+                "virtual_address",
+                &format_args!("{:#018x}", self.exception.virtual_address),
+            )
+            .field(
+                "physical_address",
+                &format_args!("{:#018x}", self.exception.physical_address),
+            );
+        }
+        dbg.finish()
+    }
+}
+
 impl std::fmt::Display for VcpuExit {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self.reason {
@@ -1402,12 +3086,309 @@ impl std::fmt::Display for VcpuExit {
     }
 }
 
+/// Number of general purpose registers that can be accessed using [`Reg`].
+const REG_COUNT: usize = Reg::CPSR as usize + 1;
+
+/// Represents an exception level whose stack pointer can be accessed using [`Vcpu::get_sp`] and
+/// [`Vcpu::set_sp`].
+///
+/// EL2 is not listed since its stack pointer is not exposed by the hypervisor's system register
+/// API.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum ExceptionLevel {
+    /// Exception level 0, whose stack pointer is SP_EL0.
+    EL0,
+    /// Exception level 1, whose stack pointer is SP_EL1.
+    EL1,
+}
+
+impl From<ExceptionLevel> for SysReg {
+    fn from(el: ExceptionLevel) -> Self {
+        match el {
+            ExceptionLevel::EL0 => SysReg::SP_EL0,
+            ExceptionLevel::EL1 => SysReg::SP_EL1,
+        }
+    }
+}
+
+/// Represents a value of the `MDSCR_EL1` debug control register, with accessors for its most
+/// commonly used bits.
+///
+/// Other bits are preserved as is and can be accessed through [`MdscrEl1::bits`].
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct MdscrEl1(pub u64);
+
+impl MdscrEl1 {
+    /// Software step enable (SS), bit 0.
+    pub const SS: u64 = 1 << 0;
+    /// Traps EL0 accesses to the debug communication channel registers (TDCC), bit 12.
+    pub const TDCC: u64 = 1 << 12;
+    /// Local (kernel) debug enable (KDE), bit 13.
+    pub const KDE: u64 = 1 << 13;
+    /// Monitor debug events enable (MDE), bit 15.
+    pub const MDE: u64 = 1 << 15;
+}
+
+gen_bit_accessors!(
+    MdscrEl1,
+    SS: ss, set_ss;
+    TDCC: tdcc, set_tdcc;
+    KDE: kde, set_kde;
+    MDE: mde, set_mde;
+);
+
+/// Represents a value of the `SCTLR_EL1` system control register, with accessors for its most
+/// commonly used bits.
+///
+/// Other bits are preserved as is and can be accessed through [`SctlrEl1::bits`].
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct SctlrEl1(pub u64);
+
+impl SctlrEl1 {
+    /// MMU enable for EL1&0 stage 1 address translation (M), bit 0.
+    pub const M: u64 = 1 << 0;
+    /// Alignment check enable (A), bit 1.
+    pub const A: u64 = 1 << 1;
+    /// Data cache enable (C), bit 2.
+    pub const C: u64 = 1 << 2;
+    /// SP alignment check enable for EL1 (SA), bit 3.
+    pub const SA: u64 = 1 << 3;
+    /// SP alignment check enable for EL0 (SA0), bit 4.
+    pub const SA0: u64 = 1 << 4;
+    /// Instruction cache enable (I), bit 12.
+    pub const I: u64 = 1 << 12;
+    /// Write permission implies execute-never (WXN), bit 19.
+    pub const WXN: u64 = 1 << 19;
+    /// Big-endian data accesses at EL1 (EE), bit 25.
+    pub const EE: u64 = 1 << 25;
+}
+
+gen_bit_accessors!(
+    SctlrEl1,
+    M: m, set_m;
+    A: a, set_a;
+    C: c, set_c;
+    SA: sa, set_sa;
+    SA0: sa0, set_sa0;
+    I: i, set_i;
+    WXN: wxn, set_wxn;
+    EE: ee, set_ee;
+);
+
+/// Helpers to build values of the `CPSR` register, which holds the PSTATE of the vCPU.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct Pstate;
+
+impl Pstate {
+    /// Mode bits selecting EL1 using SP_EL1 (EL1h).
+    pub const MODE_EL1H: u64 = 0b0101;
+    /// Mode bits selecting EL1 using SP_EL0 (EL1t).
+    pub const MODE_EL1T: u64 = 0b0100;
+    /// Mode bits selecting EL0 using SP_EL0 (EL0t).
+    pub const MODE_EL0T: u64 = 0b0000;
+    /// Stack pointer selection bit of the mode, set to use the SP of the current EL.
+    pub const SPSEL: u64 = 1 << 0;
+    /// Mask of the mode bits.
+    pub const MODE_MASK: u64 = 0b1111;
+    /// Debug exception mask bit.
+    pub const D: u64 = 1 << 9;
+    /// SError interrupt mask bit.
+    pub const A: u64 = 1 << 8;
+    /// IRQ mask bit.
+    pub const I: u64 = 1 << 7;
+    /// FIQ mask bit.
+    pub const F: u64 = 1 << 6;
+    /// Mask of the D, A, I and F bits.
+    pub const DAIF: u64 = Self::D | Self::A | Self::I | Self::F;
+    /// Value after a reset: EL1h with the D, A, I and F exceptions masked.
+    pub const EL1H_RESET: u64 = Self::MODE_EL1H | Self::DAIF;
+
+    /// Returns a value selecting EL1h, with the D, A, I and F exceptions masked if `masked` is
+    /// true.
+    pub const fn el1h(masked: bool) -> u64 {
+        if masked {
+            Self::EL1H_RESET
+        } else {
+            Self::MODE_EL1H
+        }
+    }
+}
+
+/// Size of an exception vector, in bytes.
+pub const VECTOR_SIZE: u64 = 0x80;
+/// Size of an exception vector table, in bytes.
+pub const VECTOR_TABLE_SIZE: u64 = 16 * VECTOR_SIZE;
+/// Offset of the synchronous exception vector taken from the current EL using SP_EL0.
+pub const VECTOR_CURRENT_EL_SP0_SYNC: u64 = 0x000;
+/// Offset of the IRQ vector taken from the current EL using SP_EL0.
+pub const VECTOR_CURRENT_EL_SP0_IRQ: u64 = 0x080;
+/// Offset of the FIQ vector taken from the current EL using SP_EL0.
+pub const VECTOR_CURRENT_EL_SP0_FIQ: u64 = 0x100;
+/// Offset of the SError vector taken from the current EL using SP_EL0.
+pub const VECTOR_CURRENT_EL_SP0_SERROR: u64 = 0x180;
+/// Offset of the synchronous exception vector taken from the current EL using SP_ELx.
+pub const VECTOR_CURRENT_EL_SPX_SYNC: u64 = 0x200;
+/// Offset of the IRQ vector taken from the current EL using SP_ELx.
+pub const VECTOR_CURRENT_EL_SPX_IRQ: u64 = 0x280;
+/// Offset of the FIQ vector taken from the current EL using SP_ELx.
+pub const VECTOR_CURRENT_EL_SPX_FIQ: u64 = 0x300;
+/// Offset of the SError vector taken from the current EL using SP_ELx.
+pub const VECTOR_CURRENT_EL_SPX_SERROR: u64 = 0x380;
+/// Offset of the synchronous exception vector taken from a lower EL in AArch64 state.
+pub const VECTOR_LOWER_EL_AARCH64_SYNC: u64 = 0x400;
+/// Offset of the IRQ vector taken from a lower EL in AArch64 state.
+pub const VECTOR_LOWER_EL_AARCH64_IRQ: u64 = 0x480;
+/// Offset of the FIQ vector taken from a lower EL in AArch64 state.
+pub const VECTOR_LOWER_EL_AARCH64_FIQ: u64 = 0x500;
+/// Offset of the SError vector taken from a lower EL in AArch64 state.
+pub const VECTOR_LOWER_EL_AARCH64_SERROR: u64 = 0x580;
+/// Offset of the synchronous exception vector taken from a lower EL in AArch32 state.
+pub const VECTOR_LOWER_EL_AARCH32_SYNC: u64 = 0x600;
+/// Offset of the IRQ vector taken from a lower EL in AArch32 state.
+pub const VECTOR_LOWER_EL_AARCH32_IRQ: u64 = 0x680;
+/// Offset of the FIQ vector taken from a lower EL in AArch32 state.
+pub const VECTOR_LOWER_EL_AARCH32_FIQ: u64 = 0x700;
+/// Offset of the SError vector taken from a lower EL in AArch32 state.
+pub const VECTOR_LOWER_EL_AARCH32_SERROR: u64 = 0x780;
+
+/// Represents the values of all the general purpose registers of a vCPU, as listed by [`Reg`].
+///
+/// The default state has all registers set to zero.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RegState {
+    values: [u64; REG_COUNT],
+}
+
+impl Default for RegState {
+    fn default() -> Self {
+        Self {
+            values: [0; REG_COUNT],
+        }
+    }
+}
+
+impl RegState {
+    /// Returns the value of `reg` in the state.
+    pub fn get(&self, reg: Reg) -> u64 {
+        self.values[reg as usize]
+    }
+
+    /// Sets the value of `reg` in the state.
+    pub fn set(&mut self, reg: Reg, value: u64) {
+        self.values[reg as usize] = value;
+    }
+
+    /// Returns the registers whose values differ between this state and `other`, in the order
+    /// of [`Reg::all`].
+    pub fn changed_regs(&self, other: &RegState) -> Vec<Reg> {
+        Reg::all()
+            .iter()
+            .copied()
+            .filter(|&reg| self.get(reg) != other.get(reg))
+            .collect()
+    }
+}
+
+/// Statistics accumulated while running a vCPU with [`Vcpu::run_loop_with_stats`].
+///
+/// The number of instructions executed is not tracked, since it can only be obtained by
+/// single-stepping the guest (see [`Vcpu::run_counting`]).
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct RunStats {
+    /// Number of times the vCPU exited.
+    pub exits: u64,
+    /// Number of exits caused by exceptions, per exception class.
+    pub exception_classes: std::collections::BTreeMap<esr::ExceptionClass, u64>,
+    /// Time spent executing the guest, in the unit of [`Vcpu::get_exec_time`].
+    pub exec_time: u64,
+}
+
+impl RunStats {
+    /// Records `exit`, which happened after `exec_time` units of guest execution.
+    pub fn record(&mut self, exit: &VcpuExit, exec_time: u64) {
+        self.exits += 1;
+        self.exec_time += exec_time;
+        if let Some(ec) = exit.exception_class() {
+            *self.exception_classes.entry(ec).or_default() += 1;
+        }
+    }
+
+    /// Returns the number of exits caused by exceptions of class `ec`.
+    pub fn count(&self, ec: esr::ExceptionClass) -> u64 {
+        self.exception_classes.get(&ec).copied().unwrap_or(0)
+    }
+}
+
+/// Identifies a register modified on a vCPU, as reported to a register observer.
+#[cfg(feature = "trace")]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum RegKind {
+    /// A general purpose register.
+    Reg(Reg),
+    /// A system register.
+    SysReg(SysReg),
+}
+
+/// Callback invoked with a register, its old value and its new value.
+#[cfg(feature = "trace")]
+type RegObserver = Box<dyn Fn(RegKind, u64, u64)>;
+
+/// Holds the register observer of a vCPU.
+#[cfg(feature = "trace")]
+#[derive(Clone, Default)]
+struct RegObserverSlot(std::rc::Rc<std::cell::RefCell<Option<RegObserver>>>);
+
+#[cfg(feature = "trace")]
+impl RegObserverSlot {
+    /// Invokes the observer, if any, after reading the old value with `get` and writing the
+    /// new one with `set`.
+    fn observe(
+        &self,
+        kind: RegKind,
+        value: u64,
+        get: impl FnOnce() -> Result<u64>,
+        set: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let observer = self.0.borrow();
+        match observer.as_ref() {
+            Some(observer) => {
+                let old = get()?;
+                set()?;
+                observer(kind, old, value);
+                Ok(())
+            }
+            None => set(),
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+impl PartialEq for RegObserverSlot {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "trace")]
+impl Eq for RegObserverSlot {}
+
+#[cfg(feature = "trace")]
+impl std::fmt::Debug for RegObserverSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("RegObserverSlot")
+            .field(&self.0.borrow().is_some())
+            .finish()
+    }
+}
+
 /// Represents a Virtual CPU.
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Vcpu {
     vcpu: VcpuInstance,
     config: VcpuConfig,
     exit: *const hv_vcpu_exit_t,
+    #[cfg(feature = "trace")]
+    observer: RegObserverSlot,
 }
 
 impl Vcpu {
@@ -1421,7 +3402,14 @@ impl Vcpu {
         let mut vcpu = VcpuInstance(0);
         let mut exit = ptr::null_mut() as *const hv_vcpu_exit_t;
         hv_unsafe_call!(hv_vcpu_create(&mut vcpu.0, &mut exit, config.0))?;
-        Ok(Self { vcpu, exit, config })
+        LIVE_VCPUS.lock().unwrap().push(vcpu.0);
+        Ok(Self {
+            vcpu,
+            exit,
+            config,
+            #[cfg(feature = "trace")]
+            observer: RegObserverSlot::default(),
+        })
     }
 
     /// Returns the [`VcpuInstance`] associated with the Vcpu.
@@ -1441,11 +3429,231 @@ impl Vcpu {
         Ok(count)
     }
 
+    /// Returns the maximum number of vCPUs that can be created by the hypervisor.
+    ///
+    /// Unlike [`Vcpu::get_max_count`], the value is only retrieved from the hypervisor the first
+    /// time this function succeeds and is cached for the lifetime of the process.
+    pub fn max_count_cached() -> Result<u32> {
+        static MAX_COUNT: OnceLock<u32> = OnceLock::new();
+        if let Some(count) = MAX_COUNT.get() {
+            return Ok(*count);
+        }
+        let count = Self::get_max_count()?;
+        Ok(*MAX_COUNT.get_or_init(|| count))
+    }
+
     /// Starts the vCPU.
     pub fn run(&self) -> Result<()> {
         hv_unsafe_call!(hv_vcpu_run(self.vcpu.0))
     }
 
+    /// Runs the vCPU by single-stepping through the guest instructions, until either
+    /// `max_instructions` instructions have been executed or the vCPU exits for a reason other
+    /// than single-stepping.
+    ///
+    /// Returns the last exit information along with the number of instructions executed. The
+    /// debug exceptions trapping state and `MDSCR_EL1` are restored before returning. If
+    /// `max_instructions` is 0, the vCPU is not run and the current exit information is returned.
+    ///
+    /// **Note:** the vCPU exits to the host after every instruction, which makes this function
+    /// much slower than [`Vcpu::run`].
+    pub fn run_counting(&self, max_instructions: u64) -> Result<(VcpuExit, u64)> {
+        let trap_debug_exceptions = self.get_trap_debug_exceptions()?;
+        let mdscr = self.get_mdscr()?;
+        self.set_trap_debug_exceptions(true)?;
+        // Enables single-stepping in MDSCR_EL1.SS.
+        let mut stepping = mdscr;
+        stepping.set_ss(true);
+        self.set_mdscr(stepping)?;
+        let ret = self.step_loop(max_instructions);
+        self.set_mdscr(mdscr)?;
+        self.set_trap_debug_exceptions(trap_debug_exceptions)?;
+        ret
+    }
+
+    /// Underlying single-stepping loop of [`Vcpu::run_counting`].
+    fn step_loop(&self, max_instructions: u64) -> Result<(VcpuExit, u64)> {
+        let mut count = 0;
+        loop {
+            // The budget is checked before running, so that no instruction is executed with a
+            // budget of 0.
+            if count >= max_instructions {
+                return Ok((self.get_exit_info(), count));
+            }
+            // Sets PSTATE.SS so that exactly one instruction is executed before the vCPU exits.
+            let cpsr = self.get_reg(Reg::CPSR)?;
+            self.set_reg(Reg::CPSR, cpsr | (1 << 21))?;
+            self.run()?;
+            let exit = self.get_exit_info();
+            if !exit.is_software_step() {
+                return Ok((exit, count));
+            }
+            count += 1;
+        }
+    }
+
+    /// Runs the vCPU for at most `max_instructions` instructions, stopping it even if the guest
+    /// is stuck in a loop.
+    ///
+    /// Returns the last exit information along with whether the budget was exhausted, which is
+    /// `false` if the vCPU exited for another reason first. If `max_instructions` is 0, the
+    /// vCPU is not run and the current exit information is returned.
+    ///
+    /// **Note:** the budget is enforced by single-stepping the guest using
+    /// [`Vcpu::run_counting`], so the vCPU exits to the host after every instruction. This is
+    /// much slower than [`Vcpu::run`] and should be reserved to short executions.
+    pub fn run_bounded(&self, max_instructions: u64) -> Result<(VcpuExit, bool)> {
+        if max_instructions == 0 {
+            return Ok((self.get_exit_info(), true));
+        }
+        let (exit, _) = self.run_counting(max_instructions)?;
+        Ok((exit, exit.is_software_step()))
+    }
+
+    /// Runs the vCPU until it exits or until it has spent roughly `budget_ns` nanoseconds
+    /// executing the guest, in which case the returned exit reason is
+    /// [`ExitReason::CANCELED`].
+    ///
+    /// A watchdog thread forces the vCPU to exit once the remaining budget has elapsed in wall
+    /// clock time. Since the execution time returned by [`Vcpu::get_exec_time`] can only be
+    /// retrieved by the vCPU's thread, it is checked every time the vCPU exits and the guest is
+    /// resumed until the budget is actually exhausted. The budget can therefore be exceeded by
+    /// the time it takes for the vCPU to exit.
+    ///
+    /// Only the exits caused by the watchdog are resumed. Cancellations requested by other
+    /// means, e.g. with [`VirtualMachine::exit_all`], are returned to the caller.
+    ///
+    /// **Note:** if the guest exits on its own right when the watchdog fires, the exit request
+    /// can remain pending and cancel the next run of the vCPU immediately.
+    pub fn run_for_exec_time(&self, budget_ns: u64) -> Result<VcpuExit> {
+        let start = self.get_exec_time()?;
+        let instance = self.get_instance();
+        loop {
+            let remaining = budget_ns.saturating_sub(self.get_exec_time()? - start);
+            // Whether the run is still in progress, which the watchdog checks with the lock held
+            // before stopping the vCPU, so that it does not stop it once the run has returned.
+            let running = Arc::new(Mutex::new(true));
+            // Whether the watchdog stopped the vCPU.
+            let fired = Arc::new(core::sync::atomic::AtomicBool::new(false));
+            // The watchdog is woken up early when the sender is dropped after the run.
+            let (done, wait) = std::sync::mpsc::channel::<()>();
+            let watchdog = {
+                let running = running.clone();
+                let fired = fired.clone();
+                std::thread::spawn(move || {
+                    let timeout = std::time::Duration::from_nanos(remaining);
+                    if wait.recv_timeout(timeout) != Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+                    {
+                        return;
+                    }
+                    let running = running.lock().unwrap();
+                    if *running {
+                        fired.store(true, core::sync::atomic::Ordering::SeqCst);
+                        let _ = Vcpu::stop(&[instance]);
+                    }
+                })
+            };
+            let ret = self.run();
+            *running.lock().unwrap() = false;
+            drop(done);
+            let _ = watchdog.join();
+            ret?;
+            let exit = self.get_exit_info();
+            if exit.reason != ExitReason::CANCELED
+                || !fired.load(core::sync::atomic::Ordering::SeqCst)
+                || self.get_exec_time()? - start >= budget_ns
+            {
+                return Ok(exit);
+            }
+        }
+    }
+
+    /// Runs the vCPU, calling `on_wfi` every time it exits because of a `WFI` instruction.
+    ///
+    /// If `on_wfi` returns an interrupt type, the corresponding interrupt is set as pending, the
+    /// `WFI` instruction is skipped and the vCPU resumes. Otherwise, or if the vCPU exits for any
+    /// other reason, the exit information is returned.
+    ///
+    /// **Note:** the guest must have unmasked the interrupt in PSTATE for it to be taken.
+    pub fn run_with_wfi_handler<F>(&self, mut on_wfi: F) -> Result<VcpuExit>
+    where
+        F: FnMut(&Vcpu) -> Option<InterruptType>,
+    {
+        loop {
+            self.run()?;
+            let exit = self.get_exit_info();
+            if exit.is_wfx() != Some(WfxKind::Wfi) {
+                return Ok(exit);
+            }
+            match on_wfi(self) {
+                Some(intr) => {
+                    self.set_pending_interrupt(intr, true)?;
+                    self.skip_instruction()?;
+                }
+                None => return Ok(exit),
+            }
+        }
+    }
+
+    /// Runs the vCPU until it exits for a reason matching `pred`, and returns the
+    /// corresponding exit information.
+    ///
+    /// Exits that do not match `pred` are considered benign and the vCPU is resumed. For `BRK`
+    /// instructions, PC is first advanced past the breakpoint, while for other exits the vCPU
+    /// resumes from its current state. `pred` should therefore match exceptions that would be
+    /// raised again when resuming, such as aborts.
+    ///
+    /// **Note:** if the vCPU exits twice in a row at the same PC with the same syndrome, the
+    /// exit would fire again if resumed, and it is returned even if it does not match `pred`.
+    pub fn run_until<F>(&self, mut pred: F) -> Result<VcpuExit>
+    where
+        F: FnMut(&VcpuExit) -> bool,
+    {
+        let mut last = None;
+        loop {
+            self.run()?;
+            let exit = self.get_exit_info();
+            if pred(&exit) {
+                return Ok(exit);
+            }
+            let current = Some((self.get_reg(Reg::PC)?, exit.exception.syndrome));
+            if current == last {
+                return Ok(exit);
+            }
+            last = current;
+            if exit.is_breakpoint() {
+                self.skip_instruction()?;
+            }
+        }
+    }
+
+    /// Runs the vCPU in a loop, calling `on_exit` every time it exits and accumulating
+    /// statistics about the exits into `stats`.
+    ///
+    /// The vCPU resumes as long as `on_exit` returns `true`, and the last exit information is
+    /// returned otherwise. `on_exit` is responsible for handling the exit, e.g. by advancing PC
+    /// past a `BRK` instruction.
+    pub fn run_loop_with_stats<F>(&self, stats: &mut RunStats, mut on_exit: F) -> Result<VcpuExit>
+    where
+        F: FnMut(&Vcpu, &VcpuExit) -> bool,
+    {
+        loop {
+            let start = self.get_exec_time()?;
+            self.run()?;
+            let exit = self.get_exit_info();
+            stats.record(&exit, self.get_exec_time()? - start);
+            if !on_exit(self, &exit) {
+                return Ok(exit);
+            }
+        }
+    }
+
+    /// Advances PC to the next instruction, e.g. to resume execution after a trapped `WFI`.
+    pub fn skip_instruction(&self) -> Result<()> {
+        let pc = self.get_reg(Reg::PC)?;
+        self.set_reg(Reg::PC, pc.wrapping_add(4))
+    }
+
     /// Stops all vCPUs in the input array.
     pub fn stop(vcpus: &[VcpuInstance]) -> Result<()> {
         let vcpus = vcpus.iter().map(|v| v.0).collect::<Vec<hv_vcpu_t>>();
@@ -1453,10 +3661,25 @@ impl Vcpu {
     }
 
     /// Gets vCPU exit info.
+    ///
+    /// The exit information is written by the hypervisor in a structure that is overwritten
+    /// every time the vCPU runs. The returned value is a copy of this structure, which means it
+    /// can be stored and will not change when the vCPU runs again.
     pub fn get_exit_info(&self) -> VcpuExit {
         VcpuExit::from(unsafe { *self.exit })
     }
 
+    /// Returns whether the last run of the vCPU was canceled, e.g. by [`Vcpu::stop`] or
+    /// [`VirtualMachine::exit_all`].
+    ///
+    /// Cancellation requests are asynchronous: a vCPU that is not running when it is canceled
+    /// exits as soon as it runs again, and a vCPU that exits for another reason before the
+    /// request is handled reports that reason instead. This only reflects the last run, and is
+    /// meaningless before the vCPU first ran, since the exit information is then zeroed.
+    pub fn was_canceled(&self) -> bool {
+        self.get_exit_info().reason == ExitReason::CANCELED
+    }
+
     /// Gets pending interrupts for a vCPU.
     pub fn get_pending_interrupt(&self, intr: InterruptType) -> Result<bool> {
         let mut pending = false;
@@ -1477,6 +3700,20 @@ impl Vcpu {
         ))
     }
 
+    /// Gets the pending state of both IRQs and FIQs for a vCPU, as an `(irq, fiq)` tuple.
+    pub fn pending_interrupts(&self) -> Result<(bool, bool)> {
+        Ok((
+            self.get_pending_interrupt(InterruptType::IRQ)?,
+            self.get_pending_interrupt(InterruptType::FIQ)?,
+        ))
+    }
+
+    /// Clears both pending IRQs and FIQs for a vCPU.
+    pub fn clear_pending_interrupts(&self) -> Result<()> {
+        self.set_pending_interrupt(InterruptType::IRQ, false)?;
+        self.set_pending_interrupt(InterruptType::FIQ, false)
+    }
+
     /// Gets the value of a vCPU general purpose register.
     pub fn get_reg(&self, reg: Reg) -> Result<u64> {
         let mut value = 0;
@@ -1490,11 +3727,165 @@ impl Vcpu {
 
     /// Sets the value of a vCPU general purpose register.
     pub fn set_reg(&self, reg: Reg, value: u64) -> Result<()> {
-        hv_unsafe_call!(hv_vcpu_set_reg(
-            self.vcpu.0,
-            Into::<hv_reg_t>::into(reg),
-            value
-        ))
+        let set = || {
+            hv_unsafe_call!(hv_vcpu_set_reg(
+                self.vcpu.0,
+                Into::<hv_reg_t>::into(reg),
+                value
+            ))
+        };
+        #[cfg(feature = "trace")]
+        return self
+            .observer
+            .observe(RegKind::Reg(reg), value, || self.get_reg(reg), set);
+        #[cfg(not(feature = "trace"))]
+        set()
+    }
+
+    /// Gets the lower 32 bits of a vCPU general purpose register, i.e. its `W` view.
+    ///
+    /// Returns [`HypervisorError::BadArgument`] if `reg` is not one of X0 to X30.
+    pub fn get_wreg(&self, reg: Reg) -> Result<u32> {
+        if reg > Reg::X30 {
+            return Err(HypervisorError::BadArgument);
+        }
+        Ok(self.get_reg(reg)? as u32)
+    }
+
+    /// Sets the `W` view of a vCPU general purpose register. Like a write to a `W` register in
+    /// the guest, the value is zero-extended to 64 bits.
+    ///
+    /// Returns [`HypervisorError::BadArgument`] if `reg` is not one of X0 to X30.
+    pub fn set_wreg(&self, reg: Reg, value: u32) -> Result<()> {
+        if reg > Reg::X30 {
+            return Err(HypervisorError::BadArgument);
+        }
+        self.set_reg(reg, value as u64)
+    }
+
+    /// Reads the values of all the general purpose registers of the vCPU.
+    pub fn get_reg_state(&self) -> Result<RegState> {
+        let mut state = RegState::default();
+        for &reg in Reg::all() {
+            state.set(reg, self.get_reg(reg)?);
+        }
+        Ok(state)
+    }
+
+    /// Returns the values of the general purpose registers and of the main EL0 and EL1 system
+    /// registers of the vCPU, keyed by their lowercase names (e.g. `"x0"`, `"pc"` or
+    /// `"sp_el1"`), e.g. to export them to external tools.
+    pub fn dump_regs_map(&self) -> Result<std::collections::BTreeMap<String, u64>> {
+        const SYS_REGS: &[SysReg] = &[
+            SysReg::SP_EL0,
+            SysReg::SP_EL1,
+            SysReg::TPIDR_EL0,
+            SysReg::SCTLR_EL1,
+            SysReg::TCR_EL1,
+            SysReg::TTBR0_EL1,
+            SysReg::TTBR1_EL1,
+            SysReg::MAIR_EL1,
+            SysReg::VBAR_EL1,
+            SysReg::ELR_EL1,
+            SysReg::SPSR_EL1,
+            SysReg::ESR_EL1,
+            SysReg::FAR_EL1,
+            SysReg::TPIDR_EL1,
+        ];
+        let mut regs = std::collections::BTreeMap::new();
+        for &reg in Reg::all() {
+            regs.insert(format!("{:?}", reg).to_lowercase(), self.get_reg(reg)?);
+        }
+        for &reg in SYS_REGS {
+            regs.insert(format!("{:?}", reg).to_lowercase(), self.get_sys_reg(reg)?);
+        }
+        Ok(regs)
+    }
+
+    /// Writes the values of all the general purpose registers of the vCPU.
+    pub fn set_reg_state(&self, state: &RegState) -> Result<()> {
+        for &reg in Reg::all() {
+            self.set_reg(reg, state.get(reg))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the general purpose registers whose values differ between `base` and `new`,
+    /// assuming the vCPU is currently in the `base` state.
+    ///
+    /// This avoids calling into the hypervisor for registers that are left unchanged.
+    pub fn apply_reg_diff(&self, base: &RegState, new: &RegState) -> Result<()> {
+        for reg in base.changed_regs(new) {
+            self.set_reg(reg, new.get(reg))?;
+        }
+        Ok(())
+    }
+
+    /// Restores the vCPU registers to their architectural reset state, e.g. to reuse the vCPU
+    /// between fuzzing iterations without recreating it.
+    ///
+    /// This sets X0 to X30 to zero, CPSR to [`Pstate::EL1H_RESET`] (EL1h with the D, A, I and F
+    /// exceptions masked), FPSR to zero and Q0 to Q31 to zero. All other registers, such as PC,
+    /// FPCR and system registers like `SCTLR_EL1` or `TTBR0_EL1`, are left untouched and must be
+    /// reset by the caller if needed.
+    pub fn reset(&self) -> Result<()> {
+        for &reg in Reg::all().iter().filter(|&&reg| reg <= Reg::X30) {
+            self.set_reg(reg, 0)?;
+        }
+        self.set_reg(Reg::CPSR, Pstate::EL1H_RESET)?;
+        self.set_reg(Reg::FPSR, 0)?;
+        for &reg in SimdFpReg::all() {
+            self.set_simd_fp_reg_bytes(reg, [0; 16])?;
+        }
+        Ok(())
+    }
+
+    /// Prepares the vCPU to call the guest function at address `func` with the arguments `args`,
+    /// following the AArch64 procedure call standard.
+    ///
+    /// PC is set to `func`, SP_EL0 to `stack_top`, LR to `return_sentinel` and X0 to X7 to the
+    /// values in `args`. Running the vCPU afterwards executes the function until it returns to
+    /// `return_sentinel`, where a breakpoint is typically placed to stop the execution.
+    ///
+    /// Returns [`HypervisorError::BadArgument`] if more than 8 arguments are provided.
+    pub fn setup_call(
+        &self,
+        func: u64,
+        args: &[u64],
+        stack_top: u64,
+        return_sentinel: u64,
+    ) -> Result<()> {
+        const ARG_REGS: [Reg; 8] = [
+            Reg::X0,
+            Reg::X1,
+            Reg::X2,
+            Reg::X3,
+            Reg::X4,
+            Reg::X5,
+            Reg::X6,
+            Reg::X7,
+        ];
+        if args.len() > ARG_REGS.len() {
+            return Err(HypervisorError::BadArgument);
+        }
+        for (&reg, &arg) in ARG_REGS.iter().zip(args) {
+            self.set_reg(reg, arg)?;
+        }
+        self.set_sys_reg(SysReg::SP_EL0, stack_top)?;
+        self.set_reg(Reg::LR, return_sentinel)?;
+        self.set_reg(Reg::PC, func)
+    }
+
+    /// Returns the value returned by a guest function called using [`Vcpu::setup_call`], i.e.
+    /// the value of X0.
+    pub fn call_result(&self) -> Result<u64> {
+        self.get_reg(Reg::X0)
+    }
+
+    /// Returns the 128-bit value returned by a guest function called using
+    /// [`Vcpu::setup_call`], as a pair of X0 and X1.
+    pub fn call_result_pair(&self) -> Result<(u64, u64)> {
+        Ok((self.get_reg(Reg::X0)?, self.get_reg(Reg::X1)?))
     }
 
     #[cfg(feature = "simd_nightly")]
@@ -1541,6 +3932,18 @@ impl Vcpu {
         ))
     }
 
+    /// Gets the value of a vCPU floating point register as little-endian bytes, regardless of
+    /// whether the `simd_nightly` feature is enabled.
+    pub fn get_simd_fp_reg_bytes(&self, reg: SimdFpReg) -> Result<[u8; 16]> {
+        self.get_simd_fp_reg(reg).map(simd_to_bytes)
+    }
+
+    /// Sets the value of a vCPU floating point register from little-endian bytes, regardless of
+    /// whether the `simd_nightly` feature is enabled.
+    pub fn set_simd_fp_reg_bytes(&self, reg: SimdFpReg, bytes: [u8; 16]) -> Result<()> {
+        self.set_simd_fp_reg(reg, simd_from_bytes(&bytes))
+    }
+
     /// Gets the value of a vCPU system register.
     pub fn get_sys_reg(&self, reg: SysReg) -> Result<u64> {
         let mut value = 0;
@@ -1554,48 +3957,155 @@ impl Vcpu {
 
     /// Sets the value of a vCPU general purpose register.
     pub fn set_sys_reg(&self, reg: SysReg, value: u64) -> Result<()> {
-        hv_unsafe_call!(hv_vcpu_set_sys_reg(
-            self.vcpu.0,
-            Into::<hv_sys_reg_t>::into(reg),
-            value
-        ))
+        let set = || {
+            hv_unsafe_call!(hv_vcpu_set_sys_reg(
+                self.vcpu.0,
+                Into::<hv_sys_reg_t>::into(reg),
+                value
+            ))
+        };
+        #[cfg(feature = "trace")]
+        return self
+            .observer
+            .observe(RegKind::SysReg(reg), value, || self.get_sys_reg(reg), set);
+        #[cfg(not(feature = "trace"))]
+        set()
     }
 
-    /// Gets whether debug exceptions exit the guest.
-    pub fn get_trap_debug_exceptions(&self) -> Result<bool> {
-        let mut value = false;
-        hv_unsafe_call!(hv_vcpu_get_trap_debug_exceptions(self.vcpu.0, &mut value))?;
-        Ok(value)
+    /// Gets the value of the stack pointer of exception level `el`.
+    pub fn get_sp(&self, el: ExceptionLevel) -> Result<u64> {
+        self.get_sys_reg(el.into())
     }
 
-    /// Sets whether debug exceptions exit the guest.
-    pub fn set_trap_debug_exceptions(&self, value: bool) -> Result<()> {
-        hv_unsafe_call!(hv_vcpu_set_trap_debug_exceptions(self.vcpu.0, value))
+    /// Sets the value of the stack pointer of exception level `el`.
+    pub fn set_sp(&self, el: ExceptionLevel, value: u64) -> Result<()> {
+        self.set_sys_reg(el.into(), value)
     }
 
-    /// Gets whether debug-register accesses exit the guest.
-    pub fn get_trap_debug_reg_accesses(&self) -> Result<bool> {
-        let mut value = false;
-        hv_unsafe_call!(hv_vcpu_get_trap_debug_reg_accesses(self.vcpu.0, &mut value))?;
-        Ok(value)
+    /// Returns the exception level whose stack pointer is currently selected, according to the
+    /// exception level and the SP selection bit of CPSR.
+    ///
+    /// SP_EL0 is used at EL0 and at EL1 when SPSel is cleared (EL1t), while SP_EL1 is used at
+    /// EL1 when SPSel is set (EL1h). Returns [`HypervisorError::Unsupported`] for other modes,
+    /// e.g. EL2h, whose stack pointer is not handled by [`Vcpu::get_sp`].
+    fn current_sp_level(&self) -> Result<ExceptionLevel> {
+        let cpsr = self.get_reg(Reg::CPSR)?;
+        match cpsr & Pstate::MODE_MASK {
+            Pstate::MODE_EL0T | Pstate::MODE_EL1T => Ok(ExceptionLevel::EL0),
+            Pstate::MODE_EL1H => Ok(ExceptionLevel::EL1),
+            _ => Err(HypervisorError::Unsupported),
+        }
     }
 
-    /// Sets whether debug-register accesses exit the guest.
-    pub fn set_trap_debug_reg_accesses(&self, value: bool) -> Result<()> {
-        hv_unsafe_call!(hv_vcpu_set_trap_debug_reg_accesses(self.vcpu.0, value))
+    /// Gets the value of the stack pointer currently in use by the vCPU, as selected by CPSR.
+    pub fn get_sp_current(&self) -> Result<u64> {
+        self.get_sp(self.current_sp_level()?)
     }
 
-    /// Returns the cumulative execution time of a vCPU, in nanoseconds.
-    pub fn get_exec_time(&self) -> Result<u64> {
-        let mut time = 0;
-        hv_unsafe_call!(hv_vcpu_get_exec_time(self.vcpu.0, &mut time))?;
-        Ok(time)
+    /// Sets the value of the stack pointer currently in use by the vCPU, as selected by CPSR.
+    pub fn set_sp_current(&self, value: u64) -> Result<()> {
+        self.set_sp(self.current_sp_level()?, value)
     }
 
-    /// Gets the virtual timer mask.
-    pub fn get_vtimer_mask(&self) -> Result<bool> {
-        let mut vtimer_is_masked = false;
-        hv_unsafe_call!(hv_vcpu_get_vtimer_mask(self.vcpu.0, &mut vtimer_is_masked))?;
+    /// Gets the value of the `MDSCR_EL1` debug control register.
+    pub fn get_mdscr(&self) -> Result<MdscrEl1> {
+        self.get_sys_reg(SysReg::MDSCR_EL1).map(MdscrEl1)
+    }
+
+    /// Sets the value of the `MDSCR_EL1` debug control register.
+    pub fn set_mdscr(&self, mdscr: MdscrEl1) -> Result<()> {
+        self.set_sys_reg(SysReg::MDSCR_EL1, mdscr.bits())
+    }
+
+    /// Gets the value of the `SCTLR_EL1` system control register.
+    pub fn get_sctlr(&self) -> Result<SctlrEl1> {
+        self.get_sys_reg(SysReg::SCTLR_EL1).map(SctlrEl1)
+    }
+
+    /// Sets the value of the `SCTLR_EL1` system control register.
+    pub fn set_sctlr(&self, sctlr: SctlrEl1) -> Result<()> {
+        self.set_sys_reg(SysReg::SCTLR_EL1, sctlr.bits())
+    }
+
+    /// Gets the base address of the exception vector table, held by `VBAR_EL1`.
+    pub fn get_vbar(&self) -> Result<u64> {
+        self.get_sys_reg(SysReg::VBAR_EL1)
+    }
+
+    /// Sets the base address of the exception vector table, held by `VBAR_EL1`.
+    ///
+    /// The vector table must be aligned on [`VECTOR_TABLE_SIZE`] bytes.
+    pub fn set_vbar(&self, vbar: u64) -> Result<()> {
+        self.set_sys_reg(SysReg::VBAR_EL1, vbar)
+    }
+
+    /// Enables or disables the guest MMU by setting `SCTLR_EL1.M`, leaving the other bits of
+    /// the register untouched.
+    ///
+    /// The translation tables and `TCR_EL1` must be set up beforehand for the guest to keep
+    /// running once the MMU is enabled.
+    pub fn enable_mmu(&self, enabled: bool) -> Result<()> {
+        let mut sctlr = self.get_sctlr()?;
+        sctlr.set_m(enabled);
+        self.set_sctlr(sctlr)
+    }
+
+    /// Sets a callback invoked with the register, its old value and its new value every time
+    /// [`Vcpu::set_reg`] or [`Vcpu::set_sys_reg`] successfully modifies a register.
+    ///
+    /// Any previously set observer is replaced. The observer must not set or clear the observer
+    /// of the vCPU itself.
+    #[cfg(feature = "trace")]
+    pub fn set_reg_observer(&self, observer: Box<dyn Fn(RegKind, u64, u64)>) {
+        *self.observer.0.borrow_mut() = Some(observer);
+    }
+
+    /// Removes the register observer set with [`Vcpu::set_reg_observer`].
+    #[cfg(feature = "trace")]
+    pub fn clear_reg_observer(&self) {
+        *self.observer.0.borrow_mut() = None;
+    }
+
+    /// Gets whether debug exceptions exit the guest.
+    pub fn get_trap_debug_exceptions(&self) -> Result<bool> {
+        let mut value = false;
+        hv_unsafe_call!(hv_vcpu_get_trap_debug_exceptions(self.vcpu.0, &mut value))?;
+        Ok(value)
+    }
+
+    /// Sets whether debug exceptions exit the guest.
+    pub fn set_trap_debug_exceptions(&self, value: bool) -> Result<()> {
+        hv_unsafe_call!(hv_vcpu_set_trap_debug_exceptions(self.vcpu.0, value))
+    }
+
+    /// Gets whether debug-register accesses exit the guest.
+    pub fn get_trap_debug_reg_accesses(&self) -> Result<bool> {
+        let mut value = false;
+        hv_unsafe_call!(hv_vcpu_get_trap_debug_reg_accesses(self.vcpu.0, &mut value))?;
+        Ok(value)
+    }
+
+    /// Sets whether debug-register accesses exit the guest.
+    pub fn set_trap_debug_reg_accesses(&self, value: bool) -> Result<()> {
+        hv_unsafe_call!(hv_vcpu_set_trap_debug_reg_accesses(self.vcpu.0, value))
+    }
+
+    /// Returns the cumulative execution time of a vCPU, in nanoseconds.
+    pub fn get_exec_time(&self) -> Result<u64> {
+        let mut time = 0;
+        hv_unsafe_call!(hv_vcpu_get_exec_time(self.vcpu.0, &mut time))?;
+        Ok(time)
+    }
+
+    /// Returns the cumulative execution time of a vCPU, as a [`std::time::Duration`].
+    pub fn exec_time(&self) -> Result<std::time::Duration> {
+        self.get_exec_time().map(std::time::Duration::from_nanos)
+    }
+
+    /// Gets the virtual timer mask.
+    pub fn get_vtimer_mask(&self) -> Result<bool> {
+        let mut vtimer_is_masked = false;
+        hv_unsafe_call!(hv_vcpu_get_vtimer_mask(self.vcpu.0, &mut vtimer_is_masked))?;
         Ok(vtimer_is_masked)
     }
 
@@ -1615,10 +4125,36 @@ impl Vcpu {
     pub fn set_vtimer_offset(&self, vtimer_offset: u64) -> Result<()> {
         hv_unsafe_call!(hv_vcpu_set_vtimer_offset(self.vcpu.0, vtimer_offset))
     }
+
+    /// Masks the virtual timer after a [`ExitReason::VTIMER_ACTIVATED`] exit, so that the guest
+    /// can resume without exiting again until the timer is unmasked.
+    ///
+    /// See [`VcpuExit::is_vtimer`] for the recommended handling pattern.
+    pub fn handle_vtimer(&self) -> Result<()> {
+        self.set_vtimer_mask(true)
+    }
+
+    /// Sets both the vTimer offset and the virtual timer mask.
+    pub fn configure_vtimer(&self, vtimer_offset: u64, vtimer_is_masked: bool) -> Result<()> {
+        self.set_vtimer_offset(vtimer_offset)?;
+        self.set_vtimer_mask(vtimer_is_masked)
+    }
+
+    /// Returns the current value of the virtual counter as seen by the guest in `CNTVCT_EL0`.
+    ///
+    /// The framework does not expose `CNTVCT_EL0` as a system register, so the value is derived
+    /// from the host counter and the vCPU's vTimer offset.
+    pub fn read_virtual_counter(&self) -> Result<u64> {
+        let offset = self.get_vtimer_offset()?;
+        Ok(unsafe { mach_absolute_time() }.wrapping_sub(offset))
+    }
 }
 
 impl std::ops::Drop for Vcpu {
     fn drop(&mut self) {
+        // The vCPU is unregistered before being destroyed, otherwise its identifier could be
+        // reused by a vCPU created concurrently.
+        LIVE_VCPUS.lock().unwrap().retain(|&id| id != self.vcpu.0);
         hv_unsafe_call!(hv_vcpu_destroy(self.vcpu.0))
             .expect("Could not properly destroy vCPU instance");
     }
@@ -1724,6 +4260,109 @@ impl std::fmt::Display for Vcpu {
     }
 }
 
+/// A job executed by a [`VcpuThread`] on its vCPU.
+type VcpuJob = Box<dyn FnOnce(&Vcpu) + Send>;
+
+/// Represents a vCPU living on a dedicated thread.
+///
+/// Since a [`Vcpu`] can only be used from the thread that created it, this wrapper spawns a
+/// thread, creates the vCPU on it and forwards closures to it using [`VcpuThread::exec`]. The
+/// thread and its vCPU are destroyed when the wrapper is dropped.
+#[derive(Debug)]
+pub struct VcpuThread {
+    instance: VcpuInstance,
+    sender: Option<std::sync::mpsc::Sender<VcpuJob>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl VcpuThread {
+    /// Spawns a new thread and creates a vCPU on it.
+    pub fn new() -> Result<Self> {
+        let (sender, receiver) = std::sync::mpsc::channel::<VcpuJob>();
+        let (init_sender, init_receiver) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let vcpu = match Vcpu::new() {
+                Ok(vcpu) => vcpu,
+                Err(e) => {
+                    let _ = init_sender.send(Err(e));
+                    return;
+                }
+            };
+            let _ = init_sender.send(Ok(vcpu.get_instance()));
+            // Jobs are executed until the wrapper is dropped.
+            while let Ok(job) = receiver.recv() {
+                job(&vcpu);
+            }
+        });
+        let instance = match init_receiver.recv() {
+            Ok(result) => result,
+            Err(_) => Err(HypervisorError::Error),
+        };
+        let instance = match instance {
+            Ok(instance) => instance,
+            Err(e) => {
+                let _ = thread.join();
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            instance,
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the [`VcpuInstance`] of the vCPU, which can be used from any thread, e.g. with
+    /// [`Vcpu::stop`].
+    pub fn get_handle(&self) -> VcpuInstance {
+        self.instance
+    }
+
+    /// Executes `f` on the vCPU's thread and returns its result.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the vCPU's thread terminated, e.g. because a previous job panicked.
+    pub fn exec<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Vcpu) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.try_exec(f).expect("vCPU thread terminated")
+    }
+
+    /// Executes `f` on the vCPU's thread and returns its result, or
+    /// [`HypervisorError::Error`] if the vCPU's thread terminated, e.g. because a previous job
+    /// or `f` itself panicked.
+    ///
+    /// If the thread terminated before running it, `f` is dropped without being called.
+    pub fn try_exec<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Vcpu) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(Box::new(move |vcpu: &Vcpu| {
+                let _ = sender.send(f(vcpu));
+            }))
+            .map_err(|_| HypervisorError::Error)?;
+        receiver.recv().map_err(|_| HypervisorError::Error)
+    }
+}
+
+impl std::ops::Drop for VcpuThread {
+    fn drop(&mut self) {
+        // Closing the channel makes the thread exit its loop and destroy the vCPU.
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
 // -----------------------------------------------------------------------------------------------
 // Tests
 // -----------------------------------------------------------------------------------------------
@@ -1732,6 +4371,41 @@ impl std::fmt::Display for Vcpu {
 mod tests {
     use super::*;
 
+    // -------------------------------------------------------------------------------------------
+    // Errors
+
+    #[test]
+    fn error_hints() {
+        // Denied errors point to the missing entitlement...
+        let denied = HypervisorError::Denied;
+        assert!(denied.hint().unwrap().contains("entitlement"));
+        assert!(format!("{}", denied).contains("entitlement"));
+        // ... while other errors have no hint.
+        assert_eq!(HypervisorError::BadArgument.hint(), None);
+        assert_eq!(
+            format!("{}", HypervisorError::BadArgument),
+            "function call has an invalid argument (error 0xfae94003)"
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        assert_eq!(
+            HypervisorError::BadArgument.code(),
+            hv_error_t::HV_BAD_ARGUMENT as hv_return_t
+        );
+        assert_eq!(
+            HypervisorError::NotMapped.code(),
+            hv_error_t::HV_ERROR as hv_return_t
+        );
+        assert_eq!(HypervisorError::Unknown(0x1234).code(), 0x1234);
+        // Codes round-trip through the conversion from framework errors.
+        assert_eq!(
+            HypervisorError::from(HypervisorError::Denied.code()),
+            HypervisorError::Denied
+        );
+    }
+
     // -------------------------------------------------------------------------------------------
     // Virtual Machine
 
@@ -1751,6 +4425,301 @@ mod tests {
         assert!(vm3.is_ok());
     }
 
+    #[test]
+    fn vm_live_vcpus() {
+        let vm = VirtualMachine::new().unwrap();
+        assert!(vm.live_vcpus().is_empty());
+        // A vCPU created in the main thread is tracked...
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vm.live_vcpus(), vec![vcpu.get_instance()]);
+        // ... as well as the ones created in other threads.
+        let created = Arc::new(std::sync::Barrier::new(3));
+        let checked = Arc::new(std::sync::Barrier::new(3));
+        let threads = (0..2)
+            .map(|_| {
+                let created = created.clone();
+                let checked = checked.clone();
+                std::thread::spawn(move || {
+                    let _vcpu = Vcpu::new().unwrap();
+                    created.wait();
+                    checked.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+        created.wait();
+        assert_eq!(vm.live_vcpus().len(), 3);
+        checked.wait();
+        for t in threads {
+            t.join().expect("could not join thread");
+        }
+        // vCPUs destroyed with their threads are no longer tracked.
+        assert_eq!(vm.live_vcpus(), vec![vcpu.get_instance()]);
+        drop(vcpu);
+        assert!(vm.live_vcpus().is_empty());
+    }
+
+    #[test]
+    fn vm_exit_all() {
+        let vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `b .` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0x14000000), Ok(4));
+        // Starts several vCPUs looping indefinitely in their own threads.
+        let barrier = Arc::new(std::sync::Barrier::new(5));
+        let threads = (0..4)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    let vcpu = Vcpu::new().unwrap();
+                    vcpu.set_reg(Reg::PC, 0x4000).unwrap();
+                    barrier.wait();
+                    vcpu.run().unwrap();
+                    (vcpu.get_exit_info().reason, vcpu.was_canceled())
+                })
+            })
+            .collect::<Vec<_>>();
+        barrier.wait();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        // Stops all of them at once.
+        assert_eq!(vm.exit_all(), Ok(()));
+        for t in threads {
+            let (reason, canceled) = t.join().expect("could not join thread");
+            assert_eq!(reason, ExitReason::CANCELED);
+            assert!(canceled);
+        }
+    }
+
+    #[test]
+    fn vm_host_capabilities() {
+        let caps = HostCapabilities::query().unwrap();
+        assert!(caps.max_vcpu_count > 0);
+        #[cfg(feature = "macos-13-0")]
+        {
+            let max_ipa_size = caps.max_ipa_size.unwrap();
+            let default_ipa_size = caps.default_ipa_size.unwrap();
+            assert!(default_ipa_size <= max_ipa_size);
+        }
+        #[cfg(not(feature = "macos-13-0"))]
+        assert_eq!(caps.max_ipa_size, None);
+    }
+
+    #[test]
+    fn vm_run_smp() {
+        let vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `str x0, [x1]` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0xf9000020), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        // Each vCPU writes its index at a different address.
+        let exits = vm
+            .run_smp(4, |vcpu, i| {
+                vcpu.set_trap_debug_exceptions(true)?;
+                vcpu.set_reg(Reg::X0, i as u64)?;
+                vcpu.set_reg(Reg::X1, 0x4800 + i as u64 * 8)?;
+                vcpu.set_reg(Reg::PC, 0x4000)
+            })
+            .unwrap();
+        assert_eq!(exits.len(), 4);
+        for (i, exit) in exits.iter().enumerate() {
+            assert!(exit.is_breakpoint());
+            assert_eq!(mem.read_qword(0x4800 + i as u64 * 8), Ok(i as u64));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "setup of vCPU 1 failed")]
+    fn vm_run_smp_setup_panic() {
+        let vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `brk #0` instruction at address 0x4000.
+        assert_eq!(mem.write_breakpoint(0x4000, None), Ok(4));
+        // The panic of the second vCPU's setup must not block the other ones on the barrier.
+        let _ = vm.run_smp(4, |vcpu, i| {
+            assert_ne!(i, 1, "setup of vCPU 1 failed");
+            vcpu.set_trap_debug_exceptions(true)?;
+            vcpu.set_reg(Reg::PC, 0x4000)
+        });
+    }
+
+    #[test]
+    fn vm_report() {
+        let vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `brk #0` instruction at address 0x4000.
+        assert_eq!(mem.write_breakpoint(0x4000, None), Ok(4));
+        let data = MappingShared::new(0x1000).unwrap().with_label("data");
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        let report = vm.report(&vcpu, &[&mem, &data]);
+        // Registers.
+        assert!(report.contains("X0: "));
+        assert!(report.contains("PC: 0000000000004000"));
+        // Exit information.
+        assert!(report.contains("BRK (AArch64)"));
+        // Mappings.
+        assert!(report.contains("0000000000004000-0000000000008000 RWX [first: 00 00 20 d4"));
+        assert!(report.contains("unmapped (0x4000 bytes) [data]"));
+    }
+
+    #[test]
+    fn vm_load_file() {
+        let vm = VirtualMachine::new().unwrap();
+        let path = std::env::temp_dir().join(format!("applevisor-{}.bin", std::process::id()));
+        std::fs::write(&path, b"\x00\x00\x20\xd4image").unwrap();
+        let mem = vm.load_file(&path, 0x4000, MemPerms::RX).unwrap();
+        assert_eq!(mem.get_guest_addr(), Some(0x4000));
+        assert_eq!(mem.get_size(), PAGE_SIZE);
+        assert_eq!(mem.read_dword(0x4000), Ok(0xd4200000));
+        assert_eq!(mem.read_byte(0x4004), Ok(b'i'));
+        // Hypervisor errors, e.g. when mapping over an existing mapping, are reported as such.
+        let err = vm.load_file(&path, 0x4000, MemPerms::RX).unwrap_err();
+        assert!(matches!(err, loader::LoadError::Hypervisor(_)));
+        std::fs::remove_file(&path).unwrap();
+        let err = vm.load_file(&path, 0x8000, MemPerms::RX).unwrap_err();
+        assert!(
+            matches!(err, loader::LoadError::Io(e) if e.kind() == std::io::ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn vm_next_guest_addr() {
+        let vm = VirtualMachine::new().unwrap();
+        let addr1 = vm.next_guest_addr(1).unwrap();
+        let addr2 = vm.next_guest_addr(PAGE_SIZE + 1).unwrap();
+        let addr3 = vm.next_guest_addr(PAGE_SIZE).unwrap();
+        // Addresses are page-aligned and the ranges do not overlap.
+        for addr in [addr1, addr2, addr3] {
+            assert!(addr.is_multiple_of(PAGE_SIZE as u64));
+        }
+        assert!(addr2 >= addr1 + PAGE_SIZE as u64);
+        assert!(addr3 >= addr2 + 2 * PAGE_SIZE as u64);
+        // The returned addresses can be mapped.
+        let mut mem = Mapping::new(PAGE_SIZE + 1).unwrap();
+        assert_eq!(mem.map(addr2, MemPerms::RW), Ok(()));
+        assert_eq!(vm.next_guest_addr(0), Err(HypervisorError::BadArgument));
+        assert_eq!(
+            vm.next_guest_addr(1 << 36),
+            Err(HypervisorError::NoResources)
+        );
+    }
+
+    #[test]
+    fn vm_reserve_range() {
+        let vm = VirtualMachine::new().unwrap();
+        let reservation = vm.reserve_range(0x10000, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(reservation.get_guest_addr(), 0x10000);
+        assert_eq!(reservation.get_size(), 2 * PAGE_SIZE);
+        // Overlapping reservations should not work.
+        assert_eq!(
+            vm.reserve_range(0x14000, 2 * PAGE_SIZE),
+            Err(HypervisorError::Busy)
+        );
+        // Mapping memory inside the reservation should work...
+        let mut mem1 = Mapping::new(PAGE_SIZE).unwrap();
+        assert_eq!(mem1.map(0x14000, MemPerms::RW), Ok(()));
+        // ... but not across its boundaries.
+        let mut mem2 = Mapping::new(2 * PAGE_SIZE).unwrap();
+        assert_eq!(mem2.map(0xc000, MemPerms::RW), Err(HypervisorError::Busy));
+        // Once the reservation is released, the range can be used freely.
+        drop(reservation);
+        assert_eq!(mem2.map(0xc000, MemPerms::RW), Ok(()));
+        assert!(vm.reserve_range(0x18000, PAGE_SIZE).is_ok());
+    }
+
+    #[test]
+    fn vm_create_vector_table() {
+        let vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `svc #0` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0xd4000001), Ok(4));
+        // Writes a `brk #2` instruction in the exception handler at address 0x4100.
+        assert_eq!(mem.write_breakpoint(0x4100, Some(2)), Ok(4));
+        // Unaligned handlers should be rejected.
+        assert_eq!(
+            vm.create_vector_table(0x10000, 0x4102).map(|_| ()),
+            Err(HypervisorError::BadArgument)
+        );
+        // Vector tables overflowing the address space should be rejected.
+        assert_eq!(
+            vm.create_vector_table(!0x7f, 0x4100).map(|_| ()),
+            Err(HypervisorError::BadArgument)
+        );
+        let vectors = vm.create_vector_table(0x10000, 0x4100).unwrap();
+        assert_eq!(vectors.get_guest_addr(), Some(0x10000));
+        // Each vector branches to the handler.
+        assert_eq!(vectors.read_dword(0x10000), Ok(0x17ffd040));
+        assert_eq!(vectors.read_dword(0x10200), Ok(0x17ffcfc0));
+        // The exception taken on the `svc` instruction lands in the handler.
+        assert!(vcpu.set_sys_reg(SysReg::VBAR_EL1, 0x10000).is_ok());
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_exit_info().breakpoint_immediate(), Some(2));
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4100));
+    }
+
+    #[test]
+    fn vm_create_mapping() {
+        let vm = VirtualMachine::new().unwrap();
+        let mut mem = vm.create_mapping(0x4000, 0x1000, MemPerms::RW).unwrap();
+        assert_eq!(mem.get_guest_addr(), Some(0x4000));
+        assert_eq!(mem.get_perms(), MemPerms::RW);
+        // The mapping is immediately accessible.
+        assert_eq!(mem.write_qword(0x4000, 0x4142434445464748), Ok(8));
+        assert_eq!(mem.read_qword(0x4000), Ok(0x4142434445464748));
+        // Mapping errors are reported.
+        assert!(vm.create_mapping(0x4000, 0x1000, MemPerms::RW).is_err());
+        assert_eq!(
+            vm.create_mapping(0x8000, 0x1000, MemPerms::None).err(),
+            Some(HypervisorError::BadArgument)
+        );
+    }
+
+    #[test]
+    fn vm_create_stack() {
+        let vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        let (stack, sp) = vm.create_stack(0x20000, 0x1000).unwrap();
+        // The stack pointer is aligned and at the top of the region.
+        assert_eq!(stack.get_guest_addr(), Some(0x20000));
+        assert!(sp.is_multiple_of(16));
+        assert_eq!(sp, 0x20000 + stack.get_size() as u64);
+        // Stacks overflowing the address space should be rejected.
+        assert_eq!(
+            vm.create_stack(!0xfff, 0x1000).map(|_| ()),
+            Err(HypervisorError::BadArgument)
+        );
+        // Writes a `stp x0, x1, [sp, #-16]!` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0xa9bf07e0), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        // Pushing values on the stack writes them right below the initial stack pointer.
+        assert_eq!(vcpu.set_sys_reg(SysReg::SP_EL0, sp), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::X0, 0x41), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::X1, 0x42), Ok(()));
+        // Runs at EL1 using SP_EL0 as its stack pointer (EL1t).
+        assert_eq!(vcpu.set_reg(Reg::CPSR, 0x3c4), Ok(()));
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::PC, 0x4000), Ok(()));
+        assert_eq!(vcpu.run(), Ok(()));
+        assert!(vcpu.get_exit_info().is_breakpoint());
+        assert_eq!(stack.read_qword(sp - 16), Ok(0x41));
+        assert_eq!(stack.read_qword(sp - 8), Ok(0x42));
+        // Stacks cannot overlap other mappings.
+        assert!(vm.create_stack(0x4000, 0x1000).is_err());
+    }
+
     // -------------------------------------------------------------------------------------------
     // Memory Management
 
@@ -1762,12 +4731,14 @@ mod tests {
         // Mapping it at a non-page-aligned address in the guest should not work...
         assert_eq!(
             mem.map(0x1000, MemPerms::RW),
-            Err(HypervisorError::BadArgument)
+            Err(HypervisorError::Unaligned)
         );
         // ... but a page-aligned address should.
         assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
-        // Unmapping it should also work.
+        // Unmapping it should also work...
         assert_eq!(mem.unmap(), Ok(()));
+        // ... but only once.
+        assert_eq!(mem.unmap(), Err(HypervisorError::NotMapped));
         // Mapping it twice should not work though.
         assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
         assert_eq!(mem.map(0x4000, MemPerms::RW), Err(HypervisorError::Busy));
@@ -1777,6 +4748,57 @@ mod tests {
         assert_eq!(mem2.map(0x4000, MemPerms::RW), Err(HypervisorError::Error));
     }
 
+    #[test]
+    fn memory_create_page_aligned() {
+        // Creating a mapping rounds its size up to the next page boundary...
+        let mem = Mapping::new(1).unwrap();
+        assert_eq!(mem.get_size(), PAGE_SIZE);
+        // The size originally requested is still reported.
+        assert_eq!(mem.get_requested_size(), 1);
+        let mem = MappingShared::new(PAGE_SIZE + 1).unwrap();
+        assert_eq!(mem.get_size(), 2 * PAGE_SIZE);
+        assert_eq!(mem.get_requested_size(), PAGE_SIZE + 1);
+        // ... unless the strict constructor is used.
+        assert_eq!(Mapping::new_exact(1), Err(HypervisorError::BadArgument));
+        let mem = Mapping::new_exact(PAGE_SIZE).unwrap();
+        assert_eq!(mem.get_size(), PAGE_SIZE);
+        // Sizes that would overflow when rounded up are rejected.
+        assert_eq!(Mapping::new(usize::MAX), Err(HypervisorError::BadArgument));
+        // Empty mappings are rejected by all constructors.
+        assert_eq!(Mapping::new(0), Err(HypervisorError::BadArgument));
+        assert_eq!(
+            MappingShared::new(0).map(|_| ()),
+            Err(HypervisorError::BadArgument)
+        );
+        assert_eq!(Mapping::new_exact(0), Err(HypervisorError::BadArgument));
+        assert_eq!(
+            Mapping::new_aligned(0, 0x10000),
+            Err(HypervisorError::BadArgument)
+        );
+    }
+
+    #[test]
+    fn memory_create_aligned() {
+        // Host allocations can be aligned on more than a page...
+        let mem = Mapping::new_aligned(0x1000, 0x10000).unwrap();
+        assert!((mem.get_host_addr() as usize).is_multiple_of(0x10000));
+        assert_eq!(mem.get_size(), PAGE_SIZE);
+        let mem = MappingShared::new_aligned(PAGE_SIZE, 0x100000).unwrap();
+        assert!((mem.get_host_addr() as usize).is_multiple_of(0x100000));
+        // ... but never on less.
+        let mem = Mapping::new_aligned(0x1000, 0x10).unwrap();
+        assert!((mem.get_host_addr() as usize).is_multiple_of(PAGE_SIZE));
+        // Alignments must be powers of two.
+        assert_eq!(
+            Mapping::new_aligned(0x1000, 0x6000),
+            Err(HypervisorError::BadArgument)
+        );
+        assert_eq!(
+            MappingShared::new_aligned(0x1000, 0).map(|_| ()),
+            Err(HypervisorError::BadArgument)
+        );
+    }
+
     #[test]
     fn memory_map_same_address() {
         let _vm = VirtualMachine::new().unwrap();
@@ -1792,6 +4814,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn memory_read_write_protect() {
         let _vm = VirtualMachine::new().unwrap();
         let mut mem = Mapping::new(0x1000).unwrap();
@@ -1821,56 +4844,777 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
-    fn memory_map_unmap_threads() {
-        let mut mem1 = MappingShared::new(0x1000).unwrap();
-        mem1.map(0, MemPerms::RW).expect("could not map memory");
-        let mem2 = mem1.clone();
-        let mut mem3 = mem1.clone();
-
-        let t1 = std::thread::spawn(move || {
-            println!(
-                "write val 0xdeadbeef = {:?}",
-                mem1.write_dword(0, 0xdeadbeef)
-            );
-            std::thread::sleep(std::time::Duration::from_millis(5000));
-        });
-
-        let t2 = std::thread::spawn(move || {
-            std::thread::sleep(std::time::Duration::from_millis(2000));
-            println!("read val = {:?}", mem2.read_dword(0));
-            std::thread::sleep(std::time::Duration::from_millis(2000));
-            println!("read val = {:?}", mem2.read_dword(0));
-        });
-
-        let t3 = std::thread::spawn(move || {
-            std::thread::sleep(std::time::Duration::from_millis(3000));
-            println!("write val 0 = {:?}", mem3.write_dword(0, 0));
-            std::thread::sleep(std::time::Duration::from_millis(7000));
-        });
-
-        t1.join().expect("could not join 1st thread");
-        t2.join().expect("could not join 2nd thread");
-        t3.join().expect("could not join 3rd thread");
+    fn memory_write_checked() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::R), Ok(()));
+        assert_eq!(mem.get_perms(), MemPerms::R);
+        // Checked writes to read-only memory are denied...
+        assert_eq!(
+            mem.write_checked(0x4000, &[0x41]),
+            Err(HypervisorError::Denied)
+        );
+        // ... while unchecked writes still go through.
+        assert_eq!(mem.write(0x4000, &[0x41]), Ok(1));
+        // Checked writes succeed once the memory is writable.
+        assert_eq!(mem.protect(MemPerms::RW), Ok(()));
+        assert_eq!(mem.write_checked(0x4000, &[0x42]), Ok(1));
+        assert_eq!(mem.read_byte(0x4000), Ok(0x42));
     }
 
-    // -------------------------------------------------------------------------------------------
-    // Vcpu
-
     #[test]
-    fn vcpu_config_create_get_values() {
-        let config = VcpuConfig::new();
-        // Reading feature reg from the config.
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64DFR0_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64DFR1_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64ISAR0_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64ISAR1_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64MMFR0_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64MMFR1_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64MMFR2_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64PFR0_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::ID_AA64PFR1_EL1).is_ok());
-        assert!(config.get_feature_reg(FeatureReg::CTR_EL0).is_ok());
+    fn memory_protect_range() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(2 * PAGE_SIZE).unwrap();
+        // Protecting a range of unmapped memory should not work.
+        assert_eq!(
+            mem.protect_range(0x10000, PAGE_SIZE, MemPerms::R),
+            Err(HypervisorError::NotMapped)
+        );
+        assert_eq!(mem.map(0x10000, MemPerms::RW), Ok(()));
+        // Unaligned or out-of-bounds ranges should be rejected.
+        assert_eq!(
+            mem.protect_range(0x10010, PAGE_SIZE, MemPerms::R),
+            Err(HypervisorError::Unaligned)
+        );
+        assert_eq!(
+            mem.protect_range(0x14000, 0x10, MemPerms::R),
+            Err(HypervisorError::Unaligned)
+        );
+        assert_eq!(
+            mem.protect_range(0x14000, 2 * PAGE_SIZE, MemPerms::R),
+            Err(HypervisorError::OutOfBounds)
+        );
+        // Protecting the second page only should leave the first one writable.
+        assert_eq!(mem.protect_range(0x14000, PAGE_SIZE, MemPerms::R), Ok(()));
+        assert_eq!(mem.get_perms(), MemPerms::RW);
+        assert_eq!(mem.get_page_perms(0x10000), Ok(MemPerms::RW));
+        assert_eq!(mem.get_page_perms(0x14010), Ok(MemPerms::R));
+        assert_eq!(mem.get_page_perms_by_index(0), Some(MemPerms::RW));
+        assert_eq!(mem.get_page_perms_by_index(1), Some(MemPerms::R));
+        assert_eq!(mem.get_page_perms_by_index(2), None);
+        assert_eq!(
+            mem.get_page_perms(0x18000),
+            Err(HypervisorError::OutOfBounds)
+        );
+        assert_eq!(mem.write_checked(0x13ffc, &[0x41; 4]), Ok(4));
+        assert_eq!(
+            mem.write_checked(0x13ffc, &[0x41; 8]),
+            Err(HypervisorError::Denied)
+        );
+        assert_eq!(
+            mem.write_checked(0x14000, &[0x41]),
+            Err(HypervisorError::Denied)
+        );
+        // Protecting the whole mapping updates its permissions.
+        assert_eq!(
+            mem.protect_range(0x10000, 2 * PAGE_SIZE, MemPerms::RX),
+            Ok(())
+        );
+        assert_eq!(mem.get_perms(), MemPerms::RX);
+        assert_eq!(mem.get_page_perms(0x10000), Ok(MemPerms::RX));
+        assert_eq!(mem.get_page_perms_by_index(1), Some(MemPerms::RX));
+        // Page permissions are not available once the mapping is unmapped.
+        assert_eq!(mem.unmap(), Ok(()));
+        assert_eq!(mem.get_page_perms_by_index(0), None);
+    }
+
+    #[test]
+    fn memory_ordering() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut code = Mapping::new(0x1000).unwrap();
+        let mut data = Mapping::new(0x1000).unwrap();
+        assert_eq!(code.map(0x4000, MemPerms::RX), Ok(()));
+        assert_eq!(data.map(0x8000, MemPerms::RW), Ok(()));
+        assert!(code < data);
+        assert_ne!(code, data);
+        // Mappings are keyed by their placement, not by their contents.
+        let mut mappings = std::collections::BTreeMap::new();
+        mappings.insert(data.get_guest_addr(), data);
+        mappings.insert(code.get_guest_addr(), code);
+        assert_eq!(
+            mappings.keys().copied().collect::<Vec<_>>(),
+            [Some(0x4000), Some(0x8000)]
+        );
+        assert_eq!(
+            mappings.get(&Some(0x8000)).map(|m| m.get_perms()),
+            Some(MemPerms::RW)
+        );
+        let set = mappings
+            .into_values()
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(set.first().and_then(|m| m.get_guest_addr()), Some(0x4000));
+        // Unmapped mappings only compare by size.
+        let unmapped = [Mapping::new(0x1000).unwrap(), Mapping::new(0x1000).unwrap()];
+        assert_eq!(unmapped[0], unmapped[1]);
+    }
+
+    #[test]
+    fn memory_write_code() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `mov x0, #1` instruction at address 0x4000.
+        assert_eq!(mem.write_code(0x4000, &0xd2800020u32.to_le_bytes()), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_reg(Reg::X0), Ok(1));
+        // Patches the instruction with `mov x0, #2` after it has been executed.
+        assert_eq!(mem.write_code(0x4000, &0xd2800040u32.to_le_bytes()), Ok(4));
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_reg(Reg::X0), Ok(2));
+    }
+
+    #[test]
+    fn memory_write_scattered() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        // Writing to unmapped memory should not work.
+        assert_eq!(
+            mem.write_scattered(&[(0x4000, &[0x41])]),
+            Err(HypervisorError::NotMapped)
+        );
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(
+            mem.write_scattered(&[(0x4000, b"abc"), (0x5000, b"def"), (0x7ffe, b"gh")]),
+            Ok(())
+        );
+        assert_eq!(mem.read_dword(0x4000), Ok(0x00636261));
+        assert_eq!(mem.read_dword(0x5000), Ok(0x00666564));
+        assert_eq!(mem.read_word(0x7ffe), Ok(0x6867));
+        // The whole batch is rejected if one of the patches is out of bounds.
+        assert_eq!(
+            mem.write_scattered(&[(0x4000, b"ijk"), (0x7fff, b"lm")]),
+            Err(HypervisorError::OutOfBounds)
+        );
+        assert_eq!(mem.read_dword(0x4000), Ok(0x00636261));
+        assert_eq!(mem.read_word(0x7ffe), Ok(0x6867));
+    }
+
+    #[test]
+    fn memory_access_errors() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        let mut data = [0; 4];
+        // Accessing memory that is not mapped should not work.
+        assert_eq!(mem.read(0x4000, &mut data), Err(HypervisorError::NotMapped));
+        assert_eq!(mem.write(0x4000, &data), Err(HypervisorError::NotMapped));
+        assert_eq!(mem.protect(MemPerms::R), Err(HypervisorError::NotMapped));
+        // Accessing memory outside of the mapping should not work either.
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.write(0x7ffe, &data), Err(HypervisorError::OutOfBounds));
+        assert_eq!(
+            mem.read(0x3ffe, &mut data),
+            Err(HypervisorError::OutOfBounds)
+        );
+        assert_eq!(
+            mem.write(u64::MAX, &data),
+            Err(HypervisorError::OutOfBounds)
+        );
+        assert_eq!(
+            mem.write_checked(0x7ffe, &data),
+            Err(HypervisorError::OutOfBounds)
+        );
+        // Crate-level errors keep the framework codes they used to be returned as.
+        assert_eq!(
+            Into::<hv_return_t>::into(HypervisorError::NotMapped),
+            hv_error_t::HV_ERROR as hv_return_t
+        );
+        assert_eq!(
+            Into::<hv_return_t>::into(HypervisorError::OutOfBounds),
+            hv_error_t::HV_BAD_ARGUMENT as hv_return_t
+        );
+    }
+
+    #[test]
+    fn memory_map_address_zero() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = MappingShared::new(0x1000).unwrap();
+        // Mapping memory at address 0 works like at any other address.
+        assert_eq!(mem.map(0, MemPerms::RWX), Ok(()));
+        assert_eq!(mem.get_guest_addr(), Some(0));
+        assert_eq!(mem.guest_range(), Some(0..PAGE_SIZE as u64));
+        assert!(mem.contains(0));
+        assert_eq!(mem.write_dword(0, 0xd2800840), Ok(4));
+        assert_eq!(mem.read_dword(0), Ok(0xd2800840));
+        assert_eq!(mem.get_page_perms(0), Ok(MemPerms::RWX));
+        // The guest can execute code at address 0.
+        // `mov x0, #0x42` followed by `brk #0`.
+        assert_eq!(mem.write_breakpoint(4, None), Ok(4));
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::PC, 0), Ok(()));
+        assert_eq!(vcpu.run(), Ok(()));
+        assert!(vcpu.get_exit_info().is_breakpoint());
+        assert_eq!(vcpu.get_reg(Reg::X0), Ok(0x42));
+        // Once unmapped, address 0 is no longer accessible.
+        assert_eq!(mem.unmap(), Ok(()));
+        assert_eq!(mem.get_guest_addr(), None);
+        assert_eq!(mem.read_dword(0), Err(HypervisorError::NotMapped));
+        assert_eq!(mem.unmap(), Err(HypervisorError::NotMapped));
+    }
+
+    #[test]
+    fn memory_guest_range() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.guest_range(), None);
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.guest_range(), Some(0x4000..0x8000));
+        assert_eq!(mem.unmap(), Ok(()));
+        assert_eq!(mem.guest_range(), None);
+    }
+
+    #[test]
+    fn memory_contains_overlaps() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert!(!mem.contains(0x4000));
+        assert!(!mem.overlaps(0x4000, 0x10));
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        // The start of the mapping is inclusive...
+        assert!(!mem.contains(0x3fff));
+        assert!(mem.contains(0x4000));
+        // ... and its end exclusive.
+        assert!(mem.contains(0x7fff));
+        assert!(!mem.contains(0x8000));
+        // Ranges ending at the start or starting at the end do not overlap.
+        assert!(!mem.overlaps(0x3000, 0x1000));
+        assert!(mem.overlaps(0x3000, 0x1001));
+        assert!(mem.overlaps(0x7fff, 0x1));
+        assert!(!mem.overlaps(0x8000, 0x1000));
+        // Neither do empty ranges.
+        assert!(!mem.overlaps(0x5000, 0));
+        // Ranges covering the whole mapping do.
+        assert!(mem.overlaps(0, usize::MAX));
+    }
+
+    #[test]
+    fn memory_read_partial() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        let mut data = [0; 8];
+        assert_eq!(
+            mem.read_partial(0x4000, &mut data),
+            Err(HypervisorError::NotMapped)
+        );
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.write_dword(0x7ffc, 0x41424344), Ok(4));
+        // Reading within the mapping copies everything...
+        assert_eq!(mem.read_partial(0x4000, &mut data), Ok(8));
+        // ... while reading across its end only copies the bytes in range.
+        assert_eq!(mem.read_partial(0x7ffc, &mut data), Ok(4));
+        assert_eq!(data[..4], [0x44, 0x43, 0x42, 0x41]);
+        // Nothing is copied if the start is out of range.
+        assert_eq!(mem.read_partial(0x8000, &mut data), Ok(0));
+        assert_eq!(mem.read_partial(0x3ffc, &mut data), Ok(0));
+    }
+
+    #[test]
+    fn memory_map_protect_none() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        // Mapping memory without any permission should not work...
+        assert_eq!(
+            mem.map(0x4000, MemPerms::None),
+            Err(HypervisorError::BadArgument)
+        );
+        assert_eq!(mem.get_guest_addr(), None);
+        // ... but removing all permissions from mapped memory should.
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.protect(MemPerms::None), Ok(()));
+    }
+
+    #[test]
+    fn memory_read_write_endianness() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x10000, MemPerms::RW), Ok(()));
+        // Values written in little-endian are byte-swapped when read in big-endian...
+        assert_eq!(mem.write_word(0x10000, 0x1122), Ok(2));
+        assert_eq!(mem.read_word_be(0x10000), Ok(0x2211));
+        assert_eq!(mem.write_dword(0x10010, 0x11223344), Ok(4));
+        assert_eq!(mem.read_dword_be(0x10010), Ok(0x44332211));
+        assert_eq!(mem.write_qword(0x10020, 0x1122334455667788), Ok(8));
+        assert_eq!(mem.read_qword_be(0x10020), Ok(0x8877665544332211));
+        // ... and conversely.
+        assert_eq!(mem.write_word_be(0x10030, 0x1122), Ok(2));
+        assert_eq!(mem.read_word(0x10030), Ok(0x2211));
+        assert_eq!(mem.write_dword_be(0x10040, 0x11223344), Ok(4));
+        assert_eq!(mem.read_dword(0x10040), Ok(0x44332211));
+        assert_eq!(mem.write_qword_be(0x10050, 0x1122334455667788), Ok(8));
+        assert_eq!(mem.read_qword(0x10050), Ok(0x8877665544332211));
+        // Big-endian values are stored with their most significant byte first.
+        assert_eq!(mem.read_byte(0x10050), Ok(0x11));
+    }
+
+    #[test]
+    fn memory_hexdump() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.write(0x4000, b"Hello, world!\x00\x01\xff"), Ok(16));
+        assert_eq!(
+            mem.hexdump(0x4000, 16),
+            Ok(String::from(
+                "0000000000004000: 4865 6c6c 6f2c 2077 6f72 6c64 2100 01ff  Hello, world!...\n"
+            ))
+        );
+        // Incomplete lines are padded.
+        assert_eq!(
+            mem.hexdump(0x4010, 3),
+            Ok(String::from(
+                "0000000000004010: 0000 00                                  ...\n"
+            ))
+        );
+        // Out-of-bounds ranges should be rejected.
+        assert_eq!(mem.hexdump(0x7ff0, 0x20), Err(HypervisorError::OutOfBounds));
+    }
+
+    #[test]
+    fn memory_read_write_struct() {
+        #[repr(C)]
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        struct Descriptor {
+            addr: u64,
+            len: u32,
+            flags: u16,
+            next: u16,
+        }
+
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x10000, MemPerms::RW), Ok(()));
+        let desc = Descriptor {
+            addr: 0xdeadbeef,
+            len: 0x1000,
+            flags: 0x3,
+            next: 0x1,
+        };
+        assert_eq!(unsafe { mem.write_struct(0x10010, &desc) }, Ok(16));
+        assert_eq!(unsafe { mem.read_struct::<Descriptor>(0x10010) }, Ok(desc));
+        // The fields are laid out in order.
+        assert_eq!(mem.read_qword(0x10010), Ok(0xdeadbeef));
+        assert_eq!(mem.read_dword(0x10018), Ok(0x1000));
+        assert_eq!(mem.read_word(0x1001e), Ok(0x1));
+        // Accesses must fit in the mapping.
+        let end = 0x10000 + mem.get_size() as u64 - 8;
+        assert_eq!(
+            unsafe { mem.read_struct::<Descriptor>(end) },
+            Err(HypervisorError::OutOfBounds)
+        );
+        assert_eq!(
+            unsafe { mem.write_struct(end, &desc) },
+            Err(HypervisorError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn memory_label() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap().with_label("code");
+        assert_eq!(mem.get_label(), Some(String::from("code")));
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.write(0x4000, b"Hello"), Ok(5));
+        assert_eq!(
+            mem.hexdump(0x4000, 5),
+            Ok(String::from(
+                "[code]\n0000000000004000: 4865 6c6c 6f                             Hello\n"
+            ))
+        );
+        // Labels can be changed on shared mappings too.
+        let mut shared = MappingShared::new(0x1000).unwrap();
+        assert_eq!(shared.get_label(), None);
+        shared.set_label("stack");
+        assert_eq!(shared.clone().get_label(), Some(String::from("stack")));
+    }
+
+    #[test]
+    fn memory_to_vec_load_from_slice() {
+        let mut mem = Mapping::new(PAGE_SIZE).unwrap();
+        // The content of a new mapping is zeroed.
+        assert_eq!(mem.to_vec(), vec![0; PAGE_SIZE]);
+        // Loading a pattern and reading it back.
+        let pattern = (0..PAGE_SIZE).map(|i| i as u8).collect::<Vec<u8>>();
+        assert_eq!(mem.load_from_slice(&pattern), Ok(()));
+        assert_eq!(mem.to_vec(), pattern);
+        // Loading a smaller slice only overwrites the beginning of the mapping.
+        assert_eq!(mem.load_from_slice(&[0xff; 4]), Ok(()));
+        assert_eq!(mem.to_vec()[..8], [0xff, 0xff, 0xff, 0xff, 4, 5, 6, 7]);
+        // Loading a slice larger than the mapping should fail.
+        assert_eq!(
+            mem.load_from_slice(&vec![0; PAGE_SIZE + 1]),
+            Err(HypervisorError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn memory_composite() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem1 = Mapping::new(PAGE_SIZE).unwrap();
+        let mut mem2 = MappingShared::new(PAGE_SIZE).unwrap();
+        let mut mem3 = Mapping::new(PAGE_SIZE).unwrap();
+        assert_eq!(mem1.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem2.map(0x8000, MemPerms::RW), Ok(()));
+        assert_eq!(mem3.map(0x10000, MemPerms::RW), Ok(()));
+        assert_eq!(mem1.write(0x7ffc, b"abcd"), Ok(4));
+        assert_eq!(mem2.write(0x8000, b"efgh"), Ok(4));
+        let mut composite = CompositeMemory::new(vec![&mut mem3, &mut mem2, &mut mem1]).unwrap();
+        // Reading across the boundary between two adjacent mappings.
+        let mut data = [0; 8];
+        assert_eq!(composite.read(0x7ffc, &mut data), Ok(8));
+        assert_eq!(&data, b"abcdefgh");
+        assert_eq!(composite.write(0x7ffe, b"XXXX"), Ok(4));
+        assert_eq!(composite.read(0x7ffc, &mut data), Ok(8));
+        assert_eq!(&data, b"abXXXXgh");
+        // Accesses touching a gap between mappings should not work.
+        assert_eq!(
+            composite.write(0xbffe, b"YYYY"),
+            Err(HypervisorError::OutOfBounds)
+        );
+        assert_eq!(composite.read(0xbffc, &mut data[..4]), Ok(4));
+        assert_eq!(&data[..4], &[0; 4]);
+        drop(composite);
+        // Unmapped mappings cannot be grouped.
+        let mut unmapped = Mapping::new(PAGE_SIZE).unwrap();
+        assert!(matches!(
+            CompositeMemory::new(vec![&mut mem1, &mut unmapped]),
+            Err(HypervisorError::NotMapped)
+        ));
+    }
+
+    #[test]
+    fn memory_is_vm_alive() {
+        let vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(PAGE_SIZE).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert!(mem.is_vm_alive());
+        drop(vm);
+        assert!(!mem.is_vm_alive());
+    }
+
+    #[test]
+    fn memory_deep_clone() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(PAGE_SIZE).unwrap().with_label("data");
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.write_dword(0x4000, 0xdeadbeef), Ok(4));
+        let mut copy = mem.deep_clone().unwrap();
+        assert_eq!(copy.get_guest_addr(), None);
+        assert_eq!(copy.get_size(), mem.get_size());
+        assert_eq!(copy.get_label(), Some(String::from("data")));
+        assert_ne!(copy.get_host_addr(), mem.get_host_addr());
+        // The copy can be mapped elsewhere and is independent from the original mapping.
+        assert_eq!(copy.map(0x8000, MemPerms::RW), Ok(()));
+        assert_eq!(copy.read_dword(0x8000), Ok(0xdeadbeef));
+        assert_eq!(copy.write_dword(0x8000, 0x41414141), Ok(4));
+        assert_eq!(mem.read_dword(0x4000), Ok(0xdeadbeef));
+    }
+
+    #[test]
+    fn memory_zero() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(PAGE_SIZE).unwrap();
+        let pattern = (0..PAGE_SIZE).map(|i| i as u8 | 1).collect::<Vec<u8>>();
+        assert_eq!(mem.load_from_slice(&pattern), Ok(()));
+        // Zeroing unmapped memory should not work.
+        assert_eq!(mem.zero(), Err(HypervisorError::NotMapped));
+        assert_eq!(mem.to_vec(), pattern);
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        assert_eq!(mem.zero(), Ok(()));
+        assert_eq!(mem.to_vec(), vec![0; PAGE_SIZE]);
+    }
+
+    #[test]
+    fn memory_checksum() {
+        let mut mem1 = Mapping::new(PAGE_SIZE).unwrap();
+        let mut mem2 = MappingShared::new(PAGE_SIZE).unwrap();
+        let pattern = (0..PAGE_SIZE).map(|i| i as u8).collect::<Vec<u8>>();
+        assert_eq!(mem1.load_from_slice(&pattern), Ok(()));
+        assert_eq!(mem2.load_from_slice(&pattern), Ok(()));
+        // Identical regions have identical checksums...
+        assert_eq!(mem1.checksum(), mem2.checksum());
+        assert_eq!(
+            mem1.checksum_range(0x10, 0x20),
+            mem2.checksum_range(0x10, 0x20)
+        );
+        // ... until one byte changes.
+        assert_eq!(mem2.load_from_slice(&[0xff]), Ok(()));
+        assert_ne!(mem1.checksum(), mem2.checksum());
+        assert_eq!(mem1.checksum_range(1, 0x20), mem2.checksum_range(1, 0x20));
+        // The checksum of an empty range is the FNV-1a offset basis.
+        assert_eq!(mem1.checksum_range(0, 0), Ok(0xcbf29ce484222325));
+        // Out-of-bounds ranges should be rejected.
+        assert_eq!(
+            mem1.checksum_range(PAGE_SIZE - 1, 2),
+            Err(HypervisorError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn memory_map_host() {
+        /// Page-aligned host buffer.
+        #[repr(C, align(0x4000))]
+        struct Page([u8; PAGE_SIZE]);
+
+        let vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut code = Mapping::new(0x1000).unwrap();
+        assert_eq!(code.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `ldr x0, [x1]` instruction at address 0x4000.
+        assert_eq!(code.write_dword(0x4000, 0xf9400020), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(code.write_breakpoint(0x4004, None), Ok(4));
+        // Maps a host buffer at address 0x8000 after writing a value into it.
+        let mut page = Box::new(Page([0; PAGE_SIZE]));
+        page.0[..8].copy_from_slice(&0xdeadbeefu64.to_le_bytes());
+        // Unaligned sizes and addresses should be rejected...
+        let ret = unsafe { vm.map_host(page.0.as_mut_ptr(), 0x1000, 0x8000, MemPerms::R) };
+        assert_eq!(ret, Err(HypervisorError::Unaligned));
+        let unaligned = page.0[0x1000..].as_mut_ptr();
+        let ret = unsafe { vm.map_host(unaligned, PAGE_SIZE, 0x8000, MemPerms::R) };
+        assert_eq!(ret, Err(HypervisorError::Unaligned));
+        let ret = unsafe { vm.map_host(page.0.as_mut_ptr(), PAGE_SIZE, 0x9000, MemPerms::R) };
+        assert_eq!(ret, Err(HypervisorError::Unaligned));
+        // ... as well as inaccessible mappings.
+        let ret = unsafe { vm.map_host(page.0.as_mut_ptr(), PAGE_SIZE, 0x8000, MemPerms::None) };
+        assert_eq!(ret, Err(HypervisorError::BadArgument));
+        let mem = unsafe { vm.map_host(page.0.as_mut_ptr(), PAGE_SIZE, 0x8000, MemPerms::R) };
+        let mem = mem.unwrap();
+        assert_eq!(mem.get_guest_addr(), 0x8000);
+        // Reads the value from the guest.
+        assert!(vcpu.set_reg(Reg::X1, 0x8000).is_ok());
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_reg(Reg::X0), Ok(0xdeadbeef));
+        // The host buffer remains valid once the mapping is dropped.
+        drop(mem);
+        assert_eq!(page.0[0], 0xef);
+    }
+
+    #[test]
+    fn memory_alias() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut code = Mapping::new(0x1000).unwrap();
+        assert_eq!(code.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `ldr x0, [x1]` instruction at address 0x4000.
+        assert_eq!(code.write_dword(0x4000, 0xf9400020), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(code.write_breakpoint(0x4004, None), Ok(4));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x10000, MemPerms::RW), Ok(()));
+        let alias = mem.add_alias(0x20000, MemPerms::R).unwrap();
+        assert_eq!(alias.get_guest_addr(), Some(0x20000));
+        assert_eq!(alias.get_size(), mem.get_size());
+        assert_eq!(alias.get_host_addr(), mem.get_host_addr());
+        // Aliases cannot overlap existing mappings.
+        assert!(mem.add_alias(0x10000, MemPerms::R).is_err());
+        // Writes through the mapping are visible through the alias, from the host...
+        assert_eq!(mem.write_qword(0x10008, 0xdeadbeef), Ok(8));
+        let mut data = [0; 8];
+        assert_eq!(alias.read(0x20008, &mut data), Ok(8));
+        assert_eq!(u64::from_le_bytes(data), 0xdeadbeef);
+        // ... and from the guest.
+        assert!(vcpu.set_reg(Reg::X1, 0x20008).is_ok());
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_reg(Reg::X0), Ok(0xdeadbeef));
+        // The host memory outlives the mapping while the alias exists.
+        drop(mem);
+        assert_eq!(alias.read(0x20008, &mut data), Ok(8));
+        assert_eq!(u64::from_le_bytes(data), 0xdeadbeef);
+        drop(alias);
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x20000, MemPerms::RW), Ok(()));
+    }
+
+    #[test]
+    fn memory_guest_memory_trait() {
+        /// Page-aligned host buffer.
+        #[repr(C, align(0x4000))]
+        struct Page([u8; PAGE_SIZE]);
+
+        let vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Ok(()));
+        let mut page = Box::new(Page([0; PAGE_SIZE]));
+        let borrowed = unsafe { vm.map_host(page.0.as_mut_ptr(), PAGE_SIZE, 0x8000, MemPerms::RW) };
+        let mut borrowed = borrowed.unwrap();
+        // Both kinds of memory can be accessed through the same trait object.
+        let memories: [&mut dyn GuestMemory; 2] = [&mut mem, &mut borrowed];
+        for mem in memories {
+            let guest_addr = mem.get_guest_addr().unwrap();
+            assert_eq!(mem.get_size(), PAGE_SIZE);
+            assert_eq!(mem.write(guest_addr + 0x10, &[0x41, 0x42]), Ok(2));
+            let mut data = [0; 2];
+            assert_eq!(mem.read(guest_addr + 0x10, &mut data), Ok(2));
+            assert_eq!(data, [0x41, 0x42]);
+            assert_eq!(
+                mem.write(guest_addr + PAGE_SIZE as u64 - 1, &data),
+                Err(HypervisorError::OutOfBounds)
+            );
+            assert_eq!(mem.protect(MemPerms::R), Ok(()));
+        }
+        assert_eq!(borrowed.get_perms(), MemPerms::R);
+        drop(borrowed);
+        // Writes to a borrowed mapping go to the host buffer.
+        assert_eq!(page.0[0x10..0x12], [0x41, 0x42]);
+    }
+
+    #[test]
+    fn memory_move_to_thread() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        assert_eq!(mem.write_dword(0x4000, 0xdeadbeef), Ok(4));
+        // The mapping is moved to the thread owning the vCPU and used there.
+        let t = std::thread::spawn(move || {
+            let vcpu = Vcpu::new().unwrap();
+            assert_eq!(mem.read_dword(0x4000), Ok(0xdeadbeef));
+            // Writes a `brk #0` instruction at address 0x4000.
+            assert_eq!(mem.write_breakpoint(0x4000, None), Ok(4));
+            assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+            assert_eq!(vcpu.set_reg(Reg::PC, 0x4000), Ok(()));
+            assert_eq!(vcpu.run(), Ok(()));
+            assert!(vcpu.get_exit_info().is_breakpoint());
+        });
+        t.join().expect("could not join thread");
+    }
+
+    #[test]
+    #[ignore]
+    fn memory_map_unmap_threads() {
+        let mut mem1 = MappingShared::new(0x1000).unwrap();
+        mem1.map(0, MemPerms::RW).expect("could not map memory");
+        let mem2 = mem1.clone();
+        let mut mem3 = mem1.clone();
+
+        let t1 = std::thread::spawn(move || {
+            println!(
+                "write val 0xdeadbeef = {:?}",
+                mem1.write_dword(0, 0xdeadbeef)
+            );
+            std::thread::sleep(std::time::Duration::from_millis(5000));
+        });
+
+        let t2 = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(2000));
+            println!("read val = {:?}", mem2.read_dword(0));
+            std::thread::sleep(std::time::Duration::from_millis(2000));
+            println!("read val = {:?}", mem2.read_dword(0));
+        });
+
+        let t3 = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(3000));
+            println!("write val 0 = {:?}", mem3.write_dword(0, 0));
+            std::thread::sleep(std::time::Duration::from_millis(7000));
+        });
+
+        t1.join().expect("could not join 1st thread");
+        t2.join().expect("could not join 2nd thread");
+        t3.join().expect("could not join 3rd thread");
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn memory_map_log() {
+        /// Logger that records the messages of the current thread.
+        struct CaptureLogger;
+
+        thread_local! {
+            static RECORDS: std::cell::RefCell<Vec<String>> = const {
+                std::cell::RefCell::new(Vec::new())
+            };
+        }
+
+        impl log::Log for CaptureLogger {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                RECORDS.with(|r| r.borrow_mut().push(record.args().to_string()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CaptureLogger = CaptureLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem1 = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem1.map(0x4000, MemPerms::RW), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        // Mapping memory over an existing mapping fails and is logged with its arguments.
+        assert_eq!(mem.map(0x4000, MemPerms::RW), Err(HypervisorError::Error));
+        let records = RECORDS.with(|r| r.borrow().clone());
+        let record = records
+            .iter()
+            .rev()
+            .find(|r| r.starts_with("hv_vm_map("))
+            .expect("hv_vm_map call was not logged");
+        let values = record
+            .split(", ")
+            .filter_map(|arg| arg.rsplit_once('=').map(|(_, value)| value))
+            .collect::<Vec<_>>();
+        assert!(values.contains(&"16384"));
+        assert!(values.contains(&PAGE_SIZE.to_string().as_str()));
+    }
+
+    // -------------------------------------------------------------------------------------------
+    // Vcpu
+
+    /// Builds the exit information of a vCPU that exited for `reason` with `syndrome`, faulting
+    /// at virtual address 0x1234 and physical address 0x5678.
+    pub(crate) fn vcpu_exit(reason: ExitReason, syndrome: u64) -> VcpuExit {
+        VcpuExit {
+            reason,
+            exception: VcpuExitException {
+                syndrome,
+                virtual_address: 0x1234,
+                physical_address: 0x5678,
+            },
+        }
+    }
+
+    #[test]
+    fn vcpu_config_reuse() {
+        let _vm = VirtualMachine::new().unwrap();
+        let config = VcpuConfig::new();
+        let clone = config.clone();
+        let ctr = config.get_feature_reg(FeatureReg::CTR_EL0).unwrap();
+        // The original config is consumed by the first vCPU.
+        let vcpu1 = Vcpu::with_config(config).unwrap();
+        // A thread can only own one vCPU at a time, so the first one is destroyed before the
+        // clone is used.
+        drop(vcpu1);
+        let vcpu2 = Vcpu::with_config(clone.clone()).unwrap();
+        assert!(vcpu2.get_reg(Reg::PC).is_ok());
+        // The clone remains usable once the original config is gone.
+        assert_eq!(clone.get_feature_reg(FeatureReg::CTR_EL0), Ok(ctr));
+    }
+
+    #[test]
+    fn vcpu_config_create_get_values() {
+        let config = VcpuConfig::new();
+        // Reading feature reg from the config.
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64DFR0_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64DFR1_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64ISAR0_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64ISAR1_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64MMFR0_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64MMFR1_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64MMFR2_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64PFR0_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::ID_AA64PFR1_EL1).is_ok());
+        assert!(config.get_feature_reg(FeatureReg::CTR_EL0).is_ok());
         assert!(config.get_feature_reg(FeatureReg::CLIDR_EL1).is_ok());
         assert!(config.get_feature_reg(FeatureReg::DCZID_EL0).is_ok());
         // Reading the Cache Size ID Register.
@@ -1880,6 +5624,138 @@ mod tests {
         assert!(config
             .get_ccsidr_el1_sys_reg_values(CacheType::INSTRUCTION)
             .is_ok());
+        assert_eq!(
+            config.ccsidr_for(0, CacheType::DATA),
+            config.get_ccsidr_el1_sys_reg_values(CacheType::DATA)
+        );
+        assert!(config.ccsidr_for(7, CacheType::DATA).is_ok());
+        assert_eq!(
+            config.ccsidr_for(8, CacheType::DATA),
+            Err(HypervisorError::BadArgument)
+        );
+        // Decoding the L1 data cache geometry of the host.
+        let l1d = decode_ccsidr(config.ccsidr_for(0, CacheType::DATA).unwrap());
+        assert!(l1d.line_size.is_power_of_two() && l1d.line_size >= 16);
+        assert!(l1d.associativity >= 1);
+        assert!(l1d.num_sets >= 1);
+    }
+
+    #[test]
+    fn vcpu_config_decode_ccsidr() {
+        // 64-byte lines, 8 ways and 256 sets.
+        let ccsidr = (255 << 13) | (7 << 3) | 0b010;
+        assert_eq!(
+            decode_ccsidr(ccsidr),
+            CacheGeometry {
+                line_size: 64,
+                associativity: 8,
+                num_sets: 256,
+            }
+        );
+    }
+
+    #[test]
+    fn vcpu_exit_same_cause() {
+        // Exits with identical EC, IL, ISS and ISS2 but different reserved bits have the same
+        // cause.
+        let brk1 = vcpu_exit(ExitReason::EXCEPTION, 0xf200_0000);
+        let brk2 = vcpu_exit(ExitReason::EXCEPTION, 0x0100_0000_f200_0000);
+        assert_ne!(brk1, brk2);
+        assert!(brk1.same_cause(&brk2));
+        // Exits with different ISS or ISS2 do not.
+        assert!(!brk1.same_cause(&vcpu_exit(ExitReason::EXCEPTION, 0xf200_0001)));
+        assert!(!brk1.same_cause(&vcpu_exit(ExitReason::EXCEPTION, 0x0000_0010_f200_0000)));
+        // Exits with different IL do not.
+        assert!(!brk1.same_cause(&vcpu_exit(ExitReason::EXCEPTION, 0xf000_0000)));
+        // Exits with different EC do not.
+        assert!(!brk1.same_cause(&vcpu_exit(ExitReason::EXCEPTION, 0x5a00_0000)));
+        // Exits with different reasons do not.
+        assert!(!brk1.same_cause(&vcpu_exit(ExitReason::CANCELED, 0xf200_0000)));
+        // Syndromes are irrelevant for exits that are not caused by exceptions.
+        let canceled = vcpu_exit(ExitReason::CANCELED, 0);
+        assert!(canceled.same_cause(&vcpu_exit(ExitReason::CANCELED, 0x1234)));
+    }
+
+    #[test]
+    fn vcpu_exit_predicates() {
+        // `brk #0x10`
+        let brk = vcpu_exit(ExitReason::EXCEPTION, 0xf200_0010);
+        assert!(brk.is_breakpoint());
+        assert!(!brk.is_data_abort());
+        assert!(!brk.is_hvc());
+        // Translation fault, level 3, on a write access.
+        let dabt = vcpu_exit(ExitReason::EXCEPTION, 0x9200_0047);
+        assert!(!dabt.is_breakpoint());
+        assert!(dabt.is_data_abort());
+        assert!(!dabt.is_hvc());
+        // `hvc #0`
+        let hvc = vcpu_exit(ExitReason::EXCEPTION, 0x5a00_0000);
+        assert!(!hvc.is_breakpoint());
+        assert!(!hvc.is_data_abort());
+        assert!(hvc.is_hvc());
+        // Syndromes are ignored for exits that are not caused by exceptions.
+        let canceled = vcpu_exit(ExitReason::CANCELED, 0xf200_0010);
+        assert!(!canceled.is_breakpoint());
+        assert!(!canceled.is_data_abort());
+        assert!(!canceled.is_hvc());
+    }
+
+    #[test]
+    fn vcpu_exit_debug() {
+        let exit = VcpuExit {
+            reason: ExitReason::EXCEPTION,
+            exception: VcpuExitException {
+                syndrome: 0x9200_0047,
+                virtual_address: 0x1234,
+                physical_address: 0x5678,
+            },
+        };
+        let dbg = format!("{:?}", exit);
+        assert!(dbg.contains("syndrome: 0x0000000092000047"));
+        assert!(dbg.contains("exception_class: 0x24 (data abort (lower EL))"));
+        assert!(dbg.contains("virtual_address: 0x0000000000001234"));
+        assert!(dbg.contains("physical_address: 0x0000000000005678"));
+        // Exception details are omitted for exits that are not caused by exceptions.
+        let exit = VcpuExit {
+            reason: ExitReason::CANCELED,
+            ..exit
+        };
+        assert_eq!(format!("{:?}", exit), "VcpuExit { reason: CANCELED }");
+    }
+
+    #[test]
+    fn vcpu_exit_exception_class() {
+        // `brk #0x10`
+        let brk = vcpu_exit(ExitReason::EXCEPTION, 0xf200_0010);
+        assert_eq!(brk.exception_class(), Some(esr::ExceptionClass::Brk));
+        // `svc #0`
+        let svc = vcpu_exit(ExitReason::EXCEPTION, 0x5600_0000);
+        assert_eq!(svc.exception_class(), Some(esr::ExceptionClass::Svc));
+        // Translation fault, level 3, on a write access.
+        let dabt = vcpu_exit(ExitReason::EXCEPTION, 0x9200_0047);
+        assert_eq!(
+            dabt.exception_class(),
+            Some(esr::ExceptionClass::DataAbortLowerEl)
+        );
+        // Syndromes are ignored for exits that are not caused by exceptions.
+        let canceled = vcpu_exit(ExitReason::CANCELED, 0xf200_0010);
+        assert_eq!(canceled.exception_class(), None);
+    }
+
+    #[test]
+    fn vcpu_exit_fault_addresses() {
+        // Translation fault, level 3, on a write access.
+        let dabt = vcpu_exit(ExitReason::EXCEPTION, 0x9200_0047);
+        assert_eq!(dabt.fault_addresses(), Some((0x1234, 0x5678)));
+        // Translation fault, level 3, on an instruction fetch.
+        let iabt = vcpu_exit(ExitReason::EXCEPTION, 0x8600_0007);
+        assert_eq!(iabt.fault_addresses(), Some((0x1234, 0x5678)));
+        // `brk #0x10`
+        let brk = vcpu_exit(ExitReason::EXCEPTION, 0xf200_0010);
+        assert_eq!(brk.fault_addresses(), None);
+        // Syndromes are ignored for exits that are not caused by exceptions.
+        let canceled = vcpu_exit(ExitReason::CANCELED, 0x9200_0047);
+        assert_eq!(canceled.fault_addresses(), None);
     }
 
     #[test]
@@ -1888,6 +5764,13 @@ mod tests {
         assert!(Vcpu::get_max_count().is_ok());
     }
 
+    #[test]
+    fn vcpu_get_count_cached() {
+        let count = Vcpu::max_count_cached().unwrap();
+        assert_eq!(Vcpu::max_count_cached(), Ok(count));
+        assert_eq!(Vcpu::get_max_count(), Ok(count));
+    }
+
     #[test]
     fn vcpu_create_destroy() {
         let _vm = VirtualMachine::new().unwrap();
@@ -1962,6 +5845,335 @@ mod tests {
             assert_eq!(vcpu.get_simd_fp_reg(SimdFpReg::Q3), Ok(simd4));
             assert_eq!(vcpu.get_simd_fp_reg(SimdFpReg::Q4), Ok(simd5));
         }
+        // Byte-oriented accessors behave the same in both configurations.
+        let bytes: [u8; 16] = core::array::from_fn(|i| (i as u8) * 0x11);
+        assert_eq!(vcpu.set_simd_fp_reg_bytes(SimdFpReg::Q5, bytes), Ok(()));
+        assert_eq!(vcpu.get_simd_fp_reg_bytes(SimdFpReg::Q5), Ok(bytes));
+        assert_eq!(vcpu.get_simd_fp_reg_bytes(SimdFpReg::Q0), Ok([0x1; 16]));
+        // Values built from bytes can be used with the value-oriented accessors.
+        assert_eq!(
+            vcpu.set_simd_fp_reg(SimdFpReg::Q6, simd_from_bytes(&bytes)),
+            Ok(())
+        );
+        assert_eq!(
+            vcpu.get_simd_fp_reg(SimdFpReg::Q6).map(simd_to_bytes),
+            Ok(bytes)
+        );
+    }
+
+    #[test]
+    fn vcpu_simd_conversions() {
+        let bytes: [u8; 16] = core::array::from_fn(|i| i as u8);
+        assert_eq!(simd_to_bytes(simd_from_bytes(&bytes)), bytes);
+        #[cfg(not(feature = "simd_nightly"))]
+        assert_eq!(simd_from_bytes(&bytes), 0x0f0e0d0c0b0a09080706050403020100);
+    }
+
+    #[test]
+    fn vcpu_get_set_sp() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_sp(ExceptionLevel::EL0, 0x8000), Ok(()));
+        assert_eq!(vcpu.set_sp(ExceptionLevel::EL1, 0xc000), Ok(()));
+        assert_eq!(vcpu.get_sp(ExceptionLevel::EL0), Ok(0x8000));
+        assert_eq!(vcpu.get_sp(ExceptionLevel::EL1), Ok(0xc000));
+        assert_eq!(vcpu.get_sys_reg(SysReg::SP_EL0), Ok(0x8000));
+        assert_eq!(vcpu.get_sys_reg(SysReg::SP_EL1), Ok(0xc000));
+    }
+
+    #[test]
+    fn vcpu_mdscr() {
+        let mut mdscr = MdscrEl1::default();
+        mdscr.set_ss(true);
+        assert_eq!(mdscr.bits(), 1);
+        mdscr.set_tdcc(true);
+        mdscr.set_kde(true);
+        mdscr.set_mde(true);
+        assert_eq!(mdscr.bits(), 0xb001);
+        assert!(mdscr.ss() && mdscr.tdcc() && mdscr.kde() && mdscr.mde());
+        mdscr.set_ss(false);
+        assert_eq!(mdscr, MdscrEl1(0xb000));
+        // Unnamed bits are preserved.
+        let mut mdscr = MdscrEl1(1 << 14);
+        mdscr.set_mde(true);
+        assert_eq!(mdscr.bits(), 0xc000);
+        // Values round-trip through the vCPU.
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(
+            vcpu.set_mdscr(MdscrEl1(MdscrEl1::MDE | MdscrEl1::KDE)),
+            Ok(())
+        );
+        let mdscr = vcpu.get_mdscr().unwrap();
+        assert!(mdscr.mde() && mdscr.kde() && !mdscr.ss());
+        assert_eq!(vcpu.get_sys_reg(SysReg::MDSCR_EL1), Ok(0xa000));
+    }
+
+    #[test]
+    fn vcpu_sctlr() {
+        let mut sctlr = SctlrEl1::default();
+        sctlr.set_m(true);
+        sctlr.set_c(true);
+        sctlr.set_i(true);
+        assert_eq!(sctlr.bits(), 0x1005);
+        assert!(sctlr.m() && sctlr.c() && sctlr.i() && !sctlr.sa());
+        sctlr.set_c(false);
+        assert_eq!(sctlr, SctlrEl1(0x1001));
+        // Toggling the MMU on the vCPU only changes the M bit.
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let initial = vcpu.get_sctlr().unwrap();
+        assert_eq!(vcpu.enable_mmu(true), Ok(()));
+        assert!(vcpu.get_sctlr().unwrap().m());
+        assert_eq!(vcpu.enable_mmu(false), Ok(()));
+        let sctlr = vcpu.get_sctlr().unwrap();
+        assert!(!sctlr.m());
+        assert_eq!(sctlr.bits() & !SctlrEl1::M, initial.bits() & !SctlrEl1::M);
+    }
+
+    #[test]
+    fn vcpu_vbar() {
+        let offsets = [
+            VECTOR_CURRENT_EL_SP0_SYNC,
+            VECTOR_CURRENT_EL_SP0_IRQ,
+            VECTOR_CURRENT_EL_SP0_FIQ,
+            VECTOR_CURRENT_EL_SP0_SERROR,
+            VECTOR_CURRENT_EL_SPX_SYNC,
+            VECTOR_CURRENT_EL_SPX_IRQ,
+            VECTOR_CURRENT_EL_SPX_FIQ,
+            VECTOR_CURRENT_EL_SPX_SERROR,
+            VECTOR_LOWER_EL_AARCH64_SYNC,
+            VECTOR_LOWER_EL_AARCH64_IRQ,
+            VECTOR_LOWER_EL_AARCH64_FIQ,
+            VECTOR_LOWER_EL_AARCH64_SERROR,
+            VECTOR_LOWER_EL_AARCH32_SYNC,
+            VECTOR_LOWER_EL_AARCH32_IRQ,
+            VECTOR_LOWER_EL_AARCH32_FIQ,
+            VECTOR_LOWER_EL_AARCH32_SERROR,
+        ];
+        for (i, offset) in offsets.into_iter().enumerate() {
+            assert_eq!(offset, i as u64 * 0x80);
+        }
+        assert_eq!(
+            VECTOR_LOWER_EL_AARCH32_SERROR + VECTOR_SIZE,
+            VECTOR_TABLE_SIZE
+        );
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_vbar(0x10000), Ok(()));
+        assert_eq!(vcpu.get_vbar(), Ok(0x10000));
+        assert_eq!(vcpu.get_sys_reg(SysReg::VBAR_EL1), Ok(0x10000));
+    }
+
+    #[test]
+    fn vcpu_get_set_sp_current() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_sp(ExceptionLevel::EL0, 0x8000), Ok(()));
+        assert_eq!(vcpu.set_sp(ExceptionLevel::EL1, 0xc000), Ok(()));
+        // EL1h uses SP_EL1.
+        assert_eq!(vcpu.set_reg(Reg::CPSR, Pstate::el1h(true)), Ok(()));
+        assert_eq!(vcpu.get_sp_current(), Ok(0xc000));
+        assert_eq!(vcpu.set_sp_current(0xd000), Ok(()));
+        assert_eq!(vcpu.get_sp(ExceptionLevel::EL1), Ok(0xd000));
+        // Clearing SPSel switches to SP_EL0 (EL1t).
+        assert_eq!(
+            vcpu.set_reg(Reg::CPSR, Pstate::el1h(true) & !Pstate::SPSEL),
+            Ok(())
+        );
+        assert_eq!(vcpu.get_sp_current(), Ok(0x8000));
+        assert_eq!(vcpu.set_sp_current(0x9000), Ok(()));
+        assert_eq!(vcpu.get_sp(ExceptionLevel::EL0), Ok(0x9000));
+        assert_eq!(vcpu.get_sp(ExceptionLevel::EL1), Ok(0xd000));
+        // Other modes, e.g. EL2h, are rejected when the vCPU accepts them.
+        let el2h = Pstate::DAIF | 0b1001;
+        if vcpu.set_reg(Reg::CPSR, el2h).is_ok() && vcpu.get_reg(Reg::CPSR) == Ok(el2h) {
+            assert_eq!(vcpu.get_sp_current(), Err(HypervisorError::Unsupported));
+            assert_eq!(vcpu.set_sp_current(0), Err(HypervisorError::Unsupported));
+        }
+    }
+
+    #[test]
+    fn vcpu_pstate() {
+        assert_eq!(Pstate::el1h(false) & Pstate::MODE_MASK, 0b0101);
+        assert_eq!(Pstate::el1h(false) & Pstate::DAIF, 0);
+        assert_eq!(Pstate::el1h(true) & Pstate::MODE_MASK, 0b0101);
+        assert_eq!(Pstate::el1h(true) & Pstate::DAIF, 0b1111 << 6);
+        assert_eq!(Pstate::el1h(true), Pstate::EL1H_RESET);
+        assert_eq!(Pstate::EL1H_RESET, 0x3c5);
+    }
+
+    #[test]
+    fn vcpu_dump_regs_map() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_reg(Reg::X0, 0x41), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::PC, 0x4000), Ok(()));
+        assert_eq!(vcpu.set_sys_reg(SysReg::SP_EL1, 0x8000), Ok(()));
+        let regs = vcpu.dump_regs_map().unwrap();
+        assert_eq!(regs.get("x0"), Some(&0x41));
+        assert_eq!(regs.get("pc"), Some(&0x4000));
+        assert_eq!(regs.get("sp_el1"), Some(&0x8000));
+        assert!(regs.contains_key("x30"));
+        assert!(regs.contains_key("cpsr"));
+        assert!(regs.contains_key("sctlr_el1"));
+    }
+
+    #[test]
+    fn vcpu_reset() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_reg(Reg::X0, 0x41), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::LR, 0x42), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::CPSR, 0x3c4), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::PC, 0x4000), Ok(()));
+        assert_eq!(
+            vcpu.set_simd_fp_reg_bytes(SimdFpReg::Q7, [0xff; 16]),
+            Ok(())
+        );
+        assert_eq!(vcpu.reset(), Ok(()));
+        assert_eq!(vcpu.get_reg(Reg::X0), Ok(0));
+        assert_eq!(vcpu.get_reg(Reg::LR), Ok(0));
+        assert_eq!(vcpu.get_reg(Reg::CPSR), Ok(Pstate::EL1H_RESET));
+        assert_eq!(vcpu.get_reg(Reg::FPSR), Ok(0));
+        assert_eq!(vcpu.get_simd_fp_reg_bytes(SimdFpReg::Q7), Ok([0; 16]));
+        // PC is left untouched.
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4000));
+    }
+
+    #[test]
+    fn vcpu_reg_state_diff() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let base = RegState::default();
+        assert_eq!(base.get(Reg::X0), 0);
+        assert_eq!(base.get(Reg::CPSR), 0);
+        let mut new = base;
+        new.set(Reg::X1, 0x41);
+        new.set(Reg::PC, 0x4000);
+        assert_eq!(vcpu.set_reg_state(&base), Ok(()));
+        // Only the registers that changed are written, which is also checked with the register
+        // observer when the `trace` feature is enabled.
+        assert_eq!(base.changed_regs(&new), [Reg::X1, Reg::PC]);
+        assert!(base.changed_regs(&base).is_empty());
+        #[cfg(feature = "trace")]
+        let writes = {
+            let writes = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+            let recorder = writes.clone();
+            vcpu.set_reg_observer(Box::new(move |kind, _, _| recorder.borrow_mut().push(kind)));
+            writes
+        };
+        assert_eq!(vcpu.apply_reg_diff(&base, &new), Ok(()));
+        #[cfg(feature = "trace")]
+        assert_eq!(
+            *writes.borrow(),
+            [RegKind::Reg(Reg::X1), RegKind::Reg(Reg::PC)]
+        );
+        let state = vcpu.get_reg_state().unwrap();
+        assert_eq!(state.get(Reg::X1), 0x41);
+        assert_eq!(state.get(Reg::PC), 0x4000);
+        assert_eq!(state.get(Reg::X0), 0);
+    }
+
+    #[test]
+    fn vcpu_get_set_wreg() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_reg(Reg::X0, 0xdeadbeef_cafebabe), Ok(()));
+        assert_eq!(vcpu.get_wreg(Reg::X0), Ok(0xcafebabe));
+        // Writing a W register clears the upper 32 bits of the X register.
+        assert_eq!(vcpu.set_wreg(Reg::X0, 0x12345678), Ok(()));
+        assert_eq!(vcpu.get_reg(Reg::X0), Ok(0x12345678));
+        // Only general purpose registers have a W view.
+        assert_eq!(vcpu.get_wreg(Reg::PC), Err(HypervisorError::BadArgument));
+        assert_eq!(
+            vcpu.set_wreg(Reg::CPSR, 0),
+            Err(HypervisorError::BadArgument)
+        );
+    }
+
+    #[test]
+    fn vcpu_vtimer_configuration() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Setting the vTimer offset and mask together.
+        assert_eq!(vcpu.configure_vtimer(0x1000, true), Ok(()));
+        assert_eq!(vcpu.get_vtimer_offset(), Ok(0x1000));
+        assert_eq!(vcpu.get_vtimer_mask(), Ok(true));
+        assert_eq!(vcpu.configure_vtimer(0, false), Ok(()));
+        assert_eq!(vcpu.get_vtimer_offset(), Ok(0));
+        assert_eq!(vcpu.get_vtimer_mask(), Ok(false));
+        // The virtual counter never goes backwards...
+        let first = vcpu.read_virtual_counter().unwrap();
+        let second = vcpu.read_virtual_counter().unwrap();
+        assert!(first <= second);
+        // ... and matches the value read by the guest.
+        // mrs x0, cntvct_el0
+        assert_eq!(mem.write_dword(0x4000, 0xd53be040), Ok(4));
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        assert_eq!(vcpu.set_reg(Reg::PC, 0x4000), Ok(()));
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let before = vcpu.read_virtual_counter().unwrap();
+        assert_eq!(vcpu.run(), Ok(()));
+        let after = vcpu.read_virtual_counter().unwrap();
+        let counter = vcpu.get_reg(Reg::X0).unwrap();
+        assert!(before <= counter && counter <= after);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn vcpu_reg_observer() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let changes = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let recorder = changes.clone();
+        vcpu.set_reg_observer(Box::new(move |kind, old, new| {
+            recorder.borrow_mut().push((kind, old, new))
+        }));
+        // Setting X0 twice reports both changes with their old values.
+        assert_eq!(vcpu.set_reg(Reg::X0, 0x41), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::X0, 0x42), Ok(()));
+        assert_eq!(vcpu.set_sys_reg(SysReg::SP_EL0, 0x8000), Ok(()));
+        assert_eq!(
+            *changes.borrow(),
+            [
+                (RegKind::Reg(Reg::X0), 0, 0x41),
+                (RegKind::Reg(Reg::X0), 0x41, 0x42),
+                (RegKind::SysReg(SysReg::SP_EL0), 0, 0x8000),
+            ]
+        );
+        // Changes are no longer reported once the observer is removed.
+        vcpu.clear_reg_observer();
+        assert_eq!(vcpu.set_reg(Reg::X0, 0x43), Ok(()));
+        assert_eq!(changes.borrow().len(), 3);
+    }
+
+    #[test]
+    fn vcpu_pending_interrupts() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.pending_interrupts(), Ok((false, false)));
+        assert_eq!(vcpu.set_pending_interrupt(InterruptType::IRQ, true), Ok(()));
+        assert_eq!(vcpu.pending_interrupts(), Ok((true, false)));
+        assert_eq!(vcpu.set_pending_interrupt(InterruptType::FIQ, true), Ok(()));
+        assert_eq!(vcpu.pending_interrupts(), Ok((true, true)));
+        assert_eq!(vcpu.clear_pending_interrupts(), Ok(()));
+        assert_eq!(vcpu.pending_interrupts(), Ok((false, false)));
+    }
+
+    #[test]
+    fn vcpu_thread_exec() {
+        let vm = VirtualMachine::new().unwrap();
+        let thread = VcpuThread::new().unwrap();
+        let handle = thread.get_handle();
+        assert_eq!(thread.exec(|vcpu| vcpu.set_reg(Reg::X0, 0x4242)), Ok(()));
+        assert_eq!(thread.exec(|vcpu| vcpu.get_reg(Reg::X0)), Ok(0x4242));
+        assert!(thread.exec(move |vcpu| vcpu.get_instance() == handle));
+        assert!(vm.live_vcpus().contains(&handle));
+        drop(thread);
+        assert!(!vm.live_vcpus().contains(&handle));
     }
 
     #[test]
@@ -1981,4 +6193,440 @@ mod tests {
         let _exit_info = vcpu.get_exit_info();
         assert_eq!(vcpu.get_reg(Reg::X0), Ok(0x42));
     }
+
+    #[test]
+    fn vcpu_run_breakpoints() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `brk #0x1` instruction at address 0x4000.
+        assert_eq!(mem.write_breakpoint(0x4000, Some(0x1)), Ok(4));
+        assert_eq!(mem.read_dword(0x4000), Ok(0xd4200020));
+        // Writes a `brk #0xcafe` instruction at address 0x4004.
+        assert_eq!(mem.write_breakpoint(0x4004, Some(0xcafe)), Ok(4));
+        assert_eq!(mem.read_dword(0x4004), Ok(0xd4395fc0));
+        // Hits the first breakpoint.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_exit_info().breakpoint_immediate(), Some(0x1));
+        // Hits the second breakpoint.
+        assert!(vcpu.set_reg(Reg::PC, 0x4004).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_exit_info().breakpoint_immediate(), Some(0xcafe));
+    }
+
+    #[test]
+    fn vcpu_run_wfi() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `wfi` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0xd503207f), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        // The vCPU exits on the `wfi` instruction.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        let exit = vcpu.get_exit_info();
+        assert_eq!(exit.is_wfx(), Some(WfxKind::Wfi));
+        assert!(!exit.is_breakpoint());
+        // Skipping the instruction resumes the execution up to the breakpoint.
+        assert_eq!(vcpu.skip_instruction(), Ok(()));
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4004));
+        assert!(vcpu.run().is_ok());
+        let exit = vcpu.get_exit_info();
+        assert_eq!(exit.is_wfx(), None);
+        assert!(exit.is_breakpoint());
+    }
+
+    #[test]
+    fn vcpu_run_with_wfi_handler() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `msr daifclr, #2` instruction at address 0x4000 to unmask IRQs.
+        assert_eq!(mem.write_dword(0x4000, 0xd50342ff), Ok(4));
+        // Writes a `wfi` instruction at address 0x4004.
+        assert_eq!(mem.write_dword(0x4004, 0xd503207f), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4008.
+        assert_eq!(mem.write_breakpoint(0x4008, None), Ok(4));
+        // Sets up an IRQ handler (current EL with SPx) that hits a `brk #1` instruction.
+        let mut vectors = Mapping::new(0x1000).unwrap();
+        assert_eq!(vectors.map(0x10000, MemPerms::RX), Ok(()));
+        assert_eq!(vectors.write_breakpoint(0x10280, Some(1)), Ok(4));
+        assert!(vcpu.set_sys_reg(SysReg::VBAR_EL1, 0x10000).is_ok());
+        // The vCPU parks on the `wfi` instruction until an IRQ is injected.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let mut wfi_count = 0;
+        let exit = vcpu
+            .run_with_wfi_handler(|_| {
+                wfi_count += 1;
+                Some(InterruptType::IRQ)
+            })
+            .unwrap();
+        assert_eq!(wfi_count, 1);
+        // The guest then proceeds to its IRQ handler.
+        assert_eq!(exit.breakpoint_immediate(), Some(1));
+        assert_eq!(vcpu.get_sys_reg(SysReg::ELR_EL1), Ok(0x4008));
+    }
+
+    #[test]
+    fn vcpu_handle_vtimer() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `mrs x1, cntv_ctl_el0` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0xd53be321), Ok(4));
+        // Writes a `tbz x1, #2, 0x4000` instruction at address 0x4004, looping until the timer
+        // condition is met.
+        assert_eq!(mem.write_dword(0x4004, 0x3617ffe1), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4008.
+        assert_eq!(mem.write_breakpoint(0x4008, None), Ok(4));
+        // Arms the virtual timer so that it fires immediately.
+        assert_eq!(vcpu.configure_vtimer(0, false), Ok(()));
+        assert_eq!(vcpu.set_sys_reg(SysReg::CNTV_CVAL_EL0, 0), Ok(()));
+        assert_eq!(vcpu.set_sys_reg(SysReg::CNTV_CTL_EL0, 1), Ok(()));
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        assert_eq!(vcpu.set_reg(Reg::PC, 0x4000), Ok(()));
+        // The first run exits because of the virtual timer...
+        assert_eq!(vcpu.run(), Ok(()));
+        assert!(vcpu.get_exit_info().is_vtimer());
+        // ... and the guest resumes once it has been handled.
+        assert_eq!(vcpu.handle_vtimer(), Ok(()));
+        assert_eq!(vcpu.get_vtimer_mask(), Ok(true));
+        assert_eq!(vcpu.run(), Ok(()));
+        let exit = vcpu.get_exit_info();
+        assert!(!exit.is_vtimer());
+        assert!(exit.is_breakpoint());
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4008));
+    }
+
+    #[test]
+    fn vcpu_exit_info_copy() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `brk #0x1` instruction at address 0x4000.
+        assert_eq!(mem.write_breakpoint(0x4000, Some(0x1)), Ok(4));
+        // Writes a `hvc #0` instruction at address 0x4004.
+        assert_eq!(mem.write_dword(0x4004, 0xd4000002), Ok(4));
+        // Stores the exit information of the first run.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        let stored = vcpu.get_exit_info();
+        // Running the vCPU again does not change the stored copy.
+        assert!(vcpu.set_reg(Reg::PC, 0x4004).is_ok());
+        assert!(vcpu.run().is_ok());
+        assert!(vcpu.get_exit_info().is_hvc());
+        assert_ne!(stored, vcpu.get_exit_info());
+        assert_eq!(stored.breakpoint_immediate(), Some(0x1));
+    }
+
+    #[test]
+    fn vcpu_run_counting() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `mov x0, #0x1` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0xd2800020), Ok(4));
+        // Writes a `mov x1, #0x2` instruction at address 0x4004.
+        assert_eq!(mem.write_dword(0x4004, 0xd2800041), Ok(4));
+        // Writes a `add x2, x0, x1` instruction at address 0x4008.
+        assert_eq!(mem.write_dword(0x4008, 0x8b010002), Ok(4));
+        // Writes a `brk #0` instruction at address 0x400c.
+        assert_eq!(mem.write_breakpoint(0x400c, None), Ok(4));
+        // Executes the three instructions preceding the breakpoint.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let (exit, count) = vcpu.run_counting(10).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(exit.breakpoint_immediate(), Some(0));
+        assert_eq!(vcpu.get_reg(Reg::X2), Ok(0x3));
+        // Stops after the first instruction when the budget is exhausted.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let (_, count) = vcpu.run_counting(1).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4004));
+        // Nothing is executed with an empty budget.
+        let (_, count) = vcpu.run_counting(0).unwrap();
+        assert_eq!(count, 0);
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4004));
+    }
+
+    #[test]
+    fn vcpu_instance_stop() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `b .` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0x14000000), Ok(4));
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        // The infinite loop is cancelled from another thread using the vCPU handle.
+        let handle = vcpu.get_instance();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.stop()
+        });
+        assert!(vcpu.run().is_ok());
+        assert_eq!(stopper.join().unwrap(), Ok(()));
+        assert_eq!(vcpu.get_exit_info().reason, ExitReason::CANCELED);
+        // Stopping a destroyed vCPU should not work.
+        drop(vcpu);
+        assert_eq!(handle.stop(), Err(HypervisorError::NoDevice));
+    }
+
+    #[test]
+    fn vcpu_run_until() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes two `brk #0` instructions at address 0x4000, followed by a `ldr x1, [x0]`.
+        assert_eq!(mem.write_breakpoint(0x4000, None), Ok(4));
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        assert_eq!(mem.write_dword(0x4008, 0xf9400001), Ok(4));
+        // Breakpoints are stepped over until the load from unmapped memory aborts.
+        assert!(vcpu.set_reg(Reg::X0, 0x20000).is_ok());
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let mut breakpoints = 0;
+        let exit = vcpu
+            .run_until(|exit| {
+                breakpoints += exit.is_breakpoint() as usize;
+                exit.is_data_abort()
+            })
+            .unwrap();
+        assert!(exit.is_data_abort());
+        assert_eq!(exit.exception.physical_address, 0x20000);
+        assert_eq!(breakpoints, 2);
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4008));
+        // Exits firing again at the same PC are returned even if they do not match.
+        let exit = vcpu.run_until(|_| false).unwrap();
+        assert!(exit.is_data_abort());
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4008));
+    }
+
+    #[test]
+    fn mmio_device() {
+        let vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let writes = Arc::new(Mutex::new(vec![]));
+        let recorder = writes.clone();
+        let device = vm
+            .add_mmio(
+                0x10000,
+                0x1000,
+                move |offset, is_write, value| match is_write {
+                    true => recorder.lock().unwrap().push((offset, *value)),
+                    false => *value = 0x1234_5678,
+                },
+            )
+            .unwrap();
+        assert_eq!(device.get_guest_addr(), 0x10000);
+        // Overlapping regions should not work.
+        assert_eq!(
+            vm.add_mmio(0x10800, 0x1000, |_, _, _| {}).err(),
+            Some(HypervisorError::Busy)
+        );
+        // Memory cannot be mapped over the region, otherwise accesses would not trap...
+        let mut ram = Mapping::new(0x1000).unwrap();
+        assert_eq!(ram.map(0x10000, MemPerms::RW), Err(HypervisorError::Busy));
+        // ... and regions cannot be registered over mapped memory.
+        assert_eq!(ram.map(0x30000, MemPerms::RW), Ok(()));
+        assert_eq!(
+            vm.add_mmio(0x30000, 0x1000, |_, _, _| {}).err(),
+            Some(HypervisorError::Busy)
+        );
+        assert_eq!(ram.unmap(), Ok(()));
+        assert!(vm.add_mmio(0x30000, 0x1000, |_, _, _| {}).is_ok());
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `ldr x1, [x0]` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0xf9400001), Ok(4));
+        // Writes a `str x1, [x0, #8]` instruction at address 0x4004.
+        assert_eq!(mem.write_dword(0x4004, 0xf9000401), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4008.
+        assert_eq!(mem.write_breakpoint(0x4008, None), Ok(4));
+        assert!(vcpu.set_reg(Reg::X0, 0x10000).is_ok());
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let exit = vcpu.run_with_mmio().unwrap();
+        assert!(exit.is_breakpoint());
+        assert_eq!(vcpu.get_reg(Reg::X1), Ok(0x1234_5678));
+        assert_eq!(*writes.lock().unwrap(), [(8, 0x1234_5678)]);
+        // Accesses are no longer handled once the region is dropped.
+        drop(device);
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let exit = vcpu.run_with_mmio().unwrap();
+        assert!(exit.is_data_abort());
+    }
+
+    #[test]
+    fn vcpu_run_loop_with_stats() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes three `brk #0` instructions at address 0x4000, followed by a `hvc #0`.
+        assert_eq!(mem.write_breakpoint(0x4000, None), Ok(4));
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        assert_eq!(mem.write_breakpoint(0x4008, None), Ok(4));
+        assert_eq!(mem.write_dword(0x400c, 0xd4000002), Ok(4));
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let mut stats = RunStats::default();
+        let exit = vcpu
+            .run_loop_with_stats(&mut stats, |vcpu, exit| {
+                exit.is_breakpoint() && vcpu.skip_instruction().is_ok()
+            })
+            .unwrap();
+        assert!(exit.is_hvc());
+        assert_eq!(stats.exits, 4);
+        assert_eq!(stats.count(esr::ExceptionClass::Brk), 3);
+        assert_eq!(stats.count(esr::ExceptionClass::Hvc), 1);
+        assert_eq!(stats.count(esr::ExceptionClass::Svc), 0);
+        assert!(stats.exec_time > 0);
+    }
+
+    #[test]
+    fn vcpu_exec_time() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `brk #0` instruction at address 0x4000.
+        assert_eq!(mem.write_breakpoint(0x4000, None), Ok(4));
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        assert!(vcpu.run().is_ok());
+        let time = vcpu.exec_time().unwrap();
+        assert!(!time.is_zero());
+        // The execution time does not change while the vCPU is not running.
+        assert_eq!(time.as_nanos(), vcpu.get_exec_time().unwrap() as u128);
+    }
+
+    #[test]
+    fn vcpu_run_for_exec_time() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `b .` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0x14000000), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        // The infinite loop is cancelled once the budget is exhausted.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let start = vcpu.get_exec_time().unwrap();
+        let exit = vcpu.run_for_exec_time(10_000_000).unwrap();
+        assert_eq!(exit.reason, ExitReason::CANCELED);
+        assert!(vcpu.get_exec_time().unwrap() - start >= 10_000_000);
+        // Exits happening before the end of the budget are returned as is.
+        assert!(vcpu.set_reg(Reg::PC, 0x4004).is_ok());
+        let exit = vcpu.run_for_exec_time(1_000_000_000).unwrap();
+        assert!(exit.is_breakpoint());
+        // Cancellations requested by other threads are returned before the end of the budget.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let handle = vcpu.get_instance();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            handle.stop()
+        });
+        let start = vcpu.get_exec_time().unwrap();
+        let exit = vcpu.run_for_exec_time(10_000_000_000).unwrap();
+        assert_eq!(stopper.join().unwrap(), Ok(()));
+        assert_eq!(exit.reason, ExitReason::CANCELED);
+        assert!(vcpu.get_exec_time().unwrap() - start < 10_000_000_000);
+    }
+
+    #[test]
+    fn vcpu_run_bounded() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `b .` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0x14000000), Ok(4));
+        // Writes a `brk #0` instruction at address 0x4004.
+        assert_eq!(mem.write_breakpoint(0x4004, None), Ok(4));
+        // The tight loop is stopped once the budget is exhausted.
+        assert!(vcpu.set_reg(Reg::PC, 0x4000).is_ok());
+        let (exit, exhausted) = vcpu.run_bounded(100).unwrap();
+        assert!(exhausted);
+        assert!(!exit.is_breakpoint());
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4000));
+        // Natural exits happening first are reported as such.
+        assert!(vcpu.set_reg(Reg::PC, 0x4004).is_ok());
+        let (exit, exhausted) = vcpu.run_bounded(100).unwrap();
+        assert!(!exhausted);
+        assert!(exit.is_breakpoint());
+    }
+
+    #[cfg(feature = "macos-15-0")]
+    #[test]
+    fn vcpu_get_set_actlr_el1() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        let actlr = vcpu.get_sys_reg(SysReg::ACTLR_EL1).unwrap();
+        assert_eq!(vcpu.set_sys_reg(SysReg::ACTLR_EL1, actlr), Ok(()));
+        assert_eq!(vcpu.get_sys_reg(SysReg::ACTLR_EL1), Ok(actlr));
+    }
+
+    #[test]
+    fn vcpu_sys_reg_all() {
+        let regs = SysReg::all();
+        #[cfg(not(feature = "macos-15-0"))]
+        assert_eq!(regs.len(), 112);
+        #[cfg(feature = "macos-15-0")]
+        assert_eq!(regs.len(), 113);
+        assert_eq!(regs.first(), Some(&SysReg::DBGBVR0_EL1));
+        assert_eq!(regs.last(), Some(&SysReg::SP_EL1));
+        assert!(regs.contains(&SysReg::SCTLR_EL1));
+        // Every value maps to a different system register.
+        let raw_regs = regs
+            .iter()
+            .map(|&r| Into::<hv_sys_reg_t>::into(r))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(raw_regs.len(), regs.len());
+    }
+
+    #[test]
+    fn vcpu_setup_call() {
+        let _vm = VirtualMachine::new().unwrap();
+        let vcpu = Vcpu::new().unwrap();
+        assert_eq!(vcpu.set_trap_debug_exceptions(true), Ok(()));
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RWX), Ok(()));
+        // Writes a `add x0, x0, x1` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0x8b010000), Ok(4));
+        // Writes a `ret` instruction at address 0x4004.
+        assert_eq!(mem.write_dword(0x4004, 0xd65f03c0), Ok(4));
+        // Writes a `brk #0` instruction at the return address 0x4100.
+        assert_eq!(mem.write_breakpoint(0x4100, None), Ok(4));
+        // Calling a function with more than 8 arguments should fail.
+        assert_eq!(
+            vcpu.setup_call(0x4000, &[0; 9], 0x8000, 0x4100),
+            Err(HypervisorError::BadArgument)
+        );
+        // Calls the function and waits for it to return.
+        assert_eq!(
+            vcpu.setup_call(0x4000, &[0x40, 0x2], 0x8000, 0x4100),
+            Ok(())
+        );
+        assert_eq!(vcpu.get_sys_reg(SysReg::SP_EL0), Ok(0x8000));
+        assert!(vcpu.run().is_ok());
+        assert_eq!(vcpu.get_reg(Reg::PC), Ok(0x4100));
+        assert_eq!(vcpu.call_result(), Ok(0x42));
+        assert_eq!(vcpu.call_result_pair(), Ok((0x42, 0x2)));
+    }
 }
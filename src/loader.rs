@@ -0,0 +1,72 @@
+//! Errors returned by the helpers loading guest images from the host.
+//!
+//! Loading an image can fail while accessing the host filesystem or while creating and mapping
+//! the guest memory. [`LoadError`] wraps both kinds of failures and exposes the underlying error
+//! through [`std::error::Error::source`].
+
+use crate::HypervisorError;
+
+/// Represents an error that occurred while loading a guest image.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The image could not be read from the host.
+    Io(std::io::Error),
+    /// The image could not be loaded in guest memory.
+    Hypervisor(HypervisorError),
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Hypervisor(e) => Some(e),
+        }
+    }
+}
+
+impl core::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LoadError::Io(_) => write!(f, "could not read the image"),
+            LoadError::Hypervisor(_) => write!(f, "could not load the image in guest memory"),
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<HypervisorError> for LoadError {
+    fn from(e: HypervisorError) -> Self {
+        LoadError::Hypervisor(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+    use crate::{MemPerms, VirtualMachine};
+
+    #[test]
+    fn load_error_source() {
+        let vm = VirtualMachine::new().unwrap();
+        let path = std::env::temp_dir().join(format!("applevisor-{}.missing", std::process::id()));
+        let err = vm.load_file(&path, 0x4000, MemPerms::RX).unwrap_err();
+        assert!(matches!(err, LoadError::Io(_)));
+        let source = err.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        let err = LoadError::from(HypervisorError::BadArgument);
+        assert_eq!(
+            err.source().unwrap().downcast_ref::<HypervisorError>(),
+            Some(&HypervisorError::BadArgument)
+        );
+    }
+}
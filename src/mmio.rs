@@ -0,0 +1,226 @@
+//! Emulation of memory-mapped devices.
+//!
+//! A guest address range can be registered as an MMIO region using
+//! [`VirtualMachine::add_mmio`]. The range is not backed by any memory, so guest accesses to it
+//! cause data aborts, which are forwarded to the region's handler by [`Vcpu::handle_mmio`] or
+//! [`Vcpu::run_with_mmio`].
+
+use std::sync::{Arc, Mutex};
+
+use crate::{esr, Reg, Reservation, Result, Vcpu, VcpuExit, VirtualMachine};
+
+/// Callback invoked with the offset of the access in the region, whether it is a write and the
+/// value written, or the value to return for a read.
+pub type MmioHandler = dyn Fn(u64, bool, &mut u64) + Send + Sync;
+
+/// A registered MMIO region, along with its handler.
+struct MmioEntry {
+    range: core::ops::Range<u64>,
+    handler: Arc<MmioHandler>,
+}
+
+/// MMIO regions currently registered in the process.
+static MMIO_REGIONS: Mutex<Vec<MmioEntry>> = Mutex::new(Vec::new());
+
+/// Represents a guest address range emulated by a host callback, which is unregistered when
+/// dropped.
+///
+/// Instances are created using [`VirtualMachine::add_mmio`].
+#[derive(Debug, Hash, Eq, PartialEq)]
+pub struct MmioRegion {
+    reservation: Reservation,
+}
+
+impl MmioRegion {
+    /// Retrieves the region's guest address.
+    pub fn get_guest_addr(&self) -> u64 {
+        self.reservation.get_guest_addr()
+    }
+
+    /// Retrieves the region's size.
+    pub fn get_size(&self) -> usize {
+        self.reservation.get_size()
+    }
+}
+
+impl std::ops::Drop for MmioRegion {
+    fn drop(&mut self) {
+        MMIO_REGIONS
+            .lock()
+            .unwrap()
+            .retain(|r| r.range != self.reservation.range);
+    }
+}
+
+/// Represents a guest memory access decoded from the syndrome of a data abort.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct MmioAccess {
+    /// The intermediate physical address accessed.
+    pub addr: u64,
+    /// The size of the access, in bytes.
+    pub size: usize,
+    /// Whether the access is a write.
+    pub is_write: bool,
+    /// The index of the register transferred, where 31 is the zero register.
+    pub reg: u8,
+    /// Whether the loaded value must be sign-extended.
+    pub sign_extend: bool,
+    /// Whether the register transferred is a 64-bit register.
+    pub sixty_four: bool,
+}
+
+impl MmioAccess {
+    /// Decodes the access that caused `exit`, or returns `None` if the exit was not caused by a
+    /// data abort or if its syndrome does not describe the access (ISV cleared), e.g. for
+    /// load/store pair instructions.
+    pub fn decode(exit: &VcpuExit) -> Option<Self> {
+        if !exit.is_data_abort() {
+            return None;
+        }
+        let iss = esr::iss(exit.exception.syndrome);
+        // Instruction syndrome valid (ISV).
+        if iss & (1 << 24) == 0 {
+            return None;
+        }
+        Some(Self {
+            addr: exit.exception.physical_address,
+            // Syndrome access size (SAS).
+            size: 1 << ((iss >> 22) & 0b11),
+            // Write not read (WnR).
+            is_write: iss & (1 << 6) != 0,
+            // Syndrome register transfer (SRT).
+            reg: ((iss >> 16) & 0b11111) as u8,
+            // Syndrome sign extend (SSE).
+            sign_extend: iss & (1 << 21) != 0,
+            // Sixty four bit general-purpose register transfer (SF).
+            sixty_four: iss & (1 << 15) != 0,
+        })
+    }
+
+    /// Returns the mask of the bits transferred by the access.
+    fn mask(&self) -> u64 {
+        match self.size {
+            8 => u64::MAX,
+            size => (1 << (size * 8)) - 1,
+        }
+    }
+
+    /// Returns the value loaded in the destination register for a read of `value`.
+    fn extend(&self, value: u64) -> u64 {
+        let value = value & self.mask();
+        if !self.sign_extend {
+            return value;
+        }
+        let shift = 64 - self.size as u32 * 8;
+        let value = ((value << shift) as i64 >> shift) as u64;
+        match self.sixty_four {
+            true => value,
+            false => value & 0xffff_ffff,
+        }
+    }
+}
+
+impl VirtualMachine {
+    /// Registers the `size` bytes starting at `guest_addr` as an MMIO region emulated by
+    /// `handler`, e.g. to emulate the registers of a device.
+    ///
+    /// The range is reserved like with [`VirtualMachine::reserve_range`], which means it cannot
+    /// overlap with other MMIO regions or reservations. So that guest accesses always trap, the
+    /// range cannot overlap with mapped memory either, and memory cannot be mapped anywhere in
+    /// it while the region is alive. Both cases return
+    /// [`HypervisorError::Busy`](crate::HypervisorError::Busy). Accesses are only forwarded to
+    /// `handler` by [`Vcpu::handle_mmio`].
+    pub fn add_mmio<F>(&self, guest_addr: u64, size: usize, handler: F) -> Result<MmioRegion>
+    where
+        F: Fn(u64, bool, &mut u64) + Send + Sync + 'static,
+    {
+        let reservation = crate::reserve(guest_addr, size, true)?;
+        MMIO_REGIONS.lock().unwrap().push(MmioEntry {
+            range: reservation.range.clone(),
+            handler: Arc::new(handler),
+        });
+        Ok(MmioRegion { reservation })
+    }
+}
+
+impl Vcpu {
+    /// Completes the guest access that caused `exit` if it targets a registered MMIO region.
+    ///
+    /// The region's handler is called with the value of the source register for writes, and
+    /// the value it returns is loaded in the destination register for reads. PC is then
+    /// advanced past the faulting instruction. Returns `false`, without modifying the vCPU, if
+    /// the exit is not an MMIO access that can be decoded from its syndrome.
+    pub fn handle_mmio(&self, exit: &VcpuExit) -> Result<bool> {
+        let Some(access) = MmioAccess::decode(exit) else {
+            return Ok(false);
+        };
+        // The handler is called without holding the lock, so that it can register regions.
+        let (base, handler) = match MMIO_REGIONS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| r.range.contains(&access.addr))
+        {
+            Some(region) => (region.range.start, region.handler.clone()),
+            None => return Ok(false),
+        };
+        let reg = Reg::all()
+            .get(access.reg as usize)
+            .filter(|&&r| r <= Reg::X30);
+        let mut value = match (access.is_write, reg) {
+            (true, Some(&reg)) => self.get_reg(reg)? & access.mask(),
+            _ => 0,
+        };
+        handler(access.addr - base, access.is_write, &mut value);
+        if let (false, Some(&reg)) = (access.is_write, reg) {
+            self.set_reg(reg, access.extend(value))?;
+        }
+        self.skip_instruction()?;
+        Ok(true)
+    }
+
+    /// Runs the vCPU, completing the accesses to registered MMIO regions with
+    /// [`Vcpu::handle_mmio`], until it exits for another reason.
+    pub fn run_with_mmio(&self) -> Result<VcpuExit> {
+        loop {
+            self.run()?;
+            let exit = self.get_exit_info();
+            if !self.handle_mmio(&exit)? {
+                return Ok(exit);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::vcpu_exit;
+    use crate::ExitReason;
+
+    #[test]
+    fn decode_accesses() {
+        // `str x1, [x0, #8]`
+        let access = MmioAccess::decode(&vcpu_exit(ExitReason::EXCEPTION, 0x93c1_8046)).unwrap();
+        assert_eq!(access.addr, 0x5678);
+        assert_eq!(access.size, 8);
+        assert!(access.is_write);
+        assert_eq!(access.reg, 1);
+        // `ldrsb w2, [x0]`
+        let access = MmioAccess::decode(&vcpu_exit(ExitReason::EXCEPTION, 0x9322_0006)).unwrap();
+        assert_eq!(access.size, 1);
+        assert!(!access.is_write);
+        assert_eq!(access.reg, 2);
+        assert_eq!(access.extend(0x1ff), 0xffff_ffff);
+        // Syndromes without a valid instruction syndrome cannot be decoded.
+        assert_eq!(
+            MmioAccess::decode(&vcpu_exit(ExitReason::EXCEPTION, 0x9200_0046)),
+            None
+        );
+        // `brk #0`
+        assert_eq!(
+            MmioAccess::decode(&vcpu_exit(ExitReason::EXCEPTION, 0xf200_0000)),
+            None
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Helpers to set up the stage 1 translation tables of a guest.
+//!
+//! These only compute values derived from the translation control register, `TCR_EL1`, and do
+//! not access guest memory.
+
+/// Position of the TTBR0_EL1 region size offset (T0SZ) field in `TCR_EL1`.
+pub const TCR_T0SZ_SHIFT: u32 = 0;
+
+/// Mask of the TTBR0_EL1 region size offset (T0SZ) field in `TCR_EL1`.
+pub const TCR_T0SZ_MASK: u64 = 0x3f << TCR_T0SZ_SHIFT;
+
+/// Position of the TTBR0_EL1 granule size (TG0) field in `TCR_EL1`.
+pub const TCR_TG0_SHIFT: u32 = 14;
+
+/// Mask of the TTBR0_EL1 granule size (TG0) field in `TCR_EL1`.
+pub const TCR_TG0_MASK: u64 = 0b11 << TCR_TG0_SHIFT;
+
+/// Size of a translation table descriptor, in bytes.
+const DESCRIPTOR_SIZE: usize = 8;
+
+/// Minimal alignment of a translation table base address, in bytes.
+const MIN_TABLE_ALIGNMENT: usize = 64;
+
+/// Returns the number of bits of the granule size selected by the TG0 field of `tcr`.
+///
+/// The reserved value `0b11` is treated as a 4KB granule.
+fn granule_bits(tcr: u64) -> u32 {
+    match (tcr & TCR_TG0_MASK) >> TCR_TG0_SHIFT {
+        0b01 => 16,
+        0b10 => 14,
+        _ => 12,
+    }
+}
+
+/// Returns the alignment, in bytes, required for the base address of the initial lookup level
+/// table set in `TTBR0_EL1`, according to the granule size (TG0) and input address size (T0SZ)
+/// configured in `tcr`.
+///
+/// The initial table only resolves the address bits that are not resolved by the subsequent
+/// levels, which means it can be smaller than a granule, in which case it must still be aligned
+/// on 64 bytes. For example, a 4KB granule with 48-bit input addresses (`T0SZ = 16`) requires a
+/// 4KB-aligned table, while a 4KB granule with 40-bit input addresses only requires a 64-byte
+/// aligned table of two entries.
+pub fn ttbr_alignment(tcr: u64) -> usize {
+    let granule_bits = granule_bits(tcr);
+    let input_bits = 64 - ((tcr & TCR_T0SZ_MASK) >> TCR_T0SZ_SHIFT) as u32;
+    // Each level resolves as many bits as there are descriptors in a granule-sized table.
+    let level_bits = granule_bits - DESCRIPTOR_SIZE.trailing_zeros();
+    let resolved_bits = input_bits.saturating_sub(granule_bits);
+    let initial_level_bits = match resolved_bits % level_bits {
+        0 if resolved_bits != 0 => level_bits,
+        bits => bits,
+    };
+    (DESCRIPTOR_SIZE << initial_level_bits).max(MIN_TABLE_ALIGNMENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a TCR value from its TG0 and T0SZ fields.
+    fn tcr(tg0: u64, t0sz: u64) -> u64 {
+        (tg0 << TCR_TG0_SHIFT) | (t0sz << TCR_T0SZ_SHIFT)
+    }
+
+    #[test]
+    fn ttbr_alignment_4kb() {
+        // 48-bit input addresses use four levels of full tables.
+        assert_eq!(ttbr_alignment(tcr(0b00, 16)), 0x1000);
+        // 39-bit input addresses use three levels of full tables.
+        assert_eq!(ttbr_alignment(tcr(0b00, 25)), 0x1000);
+        // 40-bit input addresses start with a table of two entries.
+        assert_eq!(ttbr_alignment(tcr(0b00, 24)), 64);
+        // 42-bit input addresses start with a table of eight entries.
+        assert_eq!(ttbr_alignment(tcr(0b00, 22)), 64);
+        // 43-bit input addresses start with a table of sixteen entries.
+        assert_eq!(ttbr_alignment(tcr(0b00, 21)), 128);
+        // Other TCR fields are ignored.
+        assert_eq!(
+            ttbr_alignment(tcr(0b00, 16) | (0b101 << 32) | (1 << 23)),
+            0x1000
+        );
+    }
+
+    #[test]
+    fn ttbr_alignment_other_granules() {
+        // 16KB granule with 47-bit input addresses.
+        assert_eq!(ttbr_alignment(tcr(0b10, 17)), 0x4000);
+        // 16KB granule with 48-bit input addresses.
+        assert_eq!(ttbr_alignment(tcr(0b10, 16)), 64);
+        // 64KB granule with 42-bit input addresses.
+        assert_eq!(ttbr_alignment(tcr(0b01, 22)), 0x10000);
+        // 64KB granule with 48-bit input addresses.
+        assert_eq!(ttbr_alignment(tcr(0b01, 16)), 512);
+    }
+}
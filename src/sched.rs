@@ -0,0 +1,132 @@
+//! Lockstep scheduling of several vCPUs.
+//!
+//! Each vCPU must be used from the thread that created it, which prevents a single thread from
+//! running several vCPUs in turn. [`Scheduler`] creates every vCPU on its own [`VcpuThread`] and
+//! advances all of them by a bounded number of instructions at each step, so that they progress
+//! at the same pace regardless of how the host schedules their threads.
+
+use std::sync::{Arc, Barrier};
+
+use crate::{Result, Vcpu, VcpuExit, VcpuThread};
+
+/// Handle on the barrier of a step, which waits on it when dropped if it has not already.
+///
+/// Jobs own their handle, so that a job dropped without running, e.g. because its vCPU's thread
+/// terminated or because it panicked, does not block the other vCPUs of the step.
+pub(crate) struct StepBarrier(Option<Arc<Barrier>>);
+
+impl StepBarrier {
+    /// Creates a handle on `barrier`.
+    pub(crate) fn new(barrier: Arc<Barrier>) -> Self {
+        Self(Some(barrier))
+    }
+
+    /// Waits for all the vCPUs of the step to be ready.
+    pub(crate) fn wait(&mut self) {
+        if let Some(barrier) = self.0.take() {
+            barrier.wait();
+        }
+    }
+}
+
+impl std::ops::Drop for StepBarrier {
+    fn drop(&mut self) {
+        self.wait();
+    }
+}
+
+/// Represents a group of vCPUs advanced together, step by step.
+///
+/// The vCPUs are destroyed along with their threads when the scheduler is dropped.
+#[derive(Debug)]
+pub struct Scheduler {
+    vcpus: Vec<VcpuThread>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler managing `count` new vCPUs, each living on its own thread.
+    pub fn new(count: usize) -> Result<Self> {
+        let vcpus = (0..count)
+            .map(|_| VcpuThread::new())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { vcpus })
+    }
+
+    /// Returns the vCPUs managed by the scheduler, in index order, e.g. to configure them with
+    /// [`VcpuThread::exec`] between steps.
+    pub fn vcpus(&self) -> &[VcpuThread] {
+        &self.vcpus
+    }
+
+    /// Runs every vCPU for at most `budget` instructions and returns, in index order, their
+    /// last exit information along with the number of instructions they executed.
+    ///
+    /// All vCPUs start running at the same time and the step ends once all of them have
+    /// stopped, either because their budget is exhausted or because they exited for another
+    /// reason. The budget is enforced using [`Vcpu::run_counting`], which means the vCPUs exit
+    /// to the host after every instruction.
+    ///
+    /// **Note:** if one of the vCPUs fails, the others still run and the first error is
+    /// returned. vCPUs whose thread terminated, e.g. because a job panicked, report
+    /// [`HypervisorError::Error`](crate::HypervisorError::Error).
+    pub fn step_all(&self, budget: u64) -> Result<Vec<(VcpuExit, u64)>> {
+        let barrier = Arc::new(Barrier::new(self.vcpus.len()));
+        std::thread::scope(|scope| {
+            let steps = self
+                .vcpus
+                .iter()
+                .map(|thread| {
+                    let mut barrier = StepBarrier::new(barrier.clone());
+                    scope.spawn(move || {
+                        thread.try_exec(move |vcpu: &Vcpu| {
+                            barrier.wait();
+                            vcpu.run_counting(budget)
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            steps
+                .into_iter()
+                .map(|s| s.join().expect("could not join scheduler thread")?)
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HypervisorError, Mappable, Mapping, MemPerms, Reg, VirtualMachine};
+
+    #[test]
+    fn scheduler_step_all() {
+        let _vm = VirtualMachine::new().unwrap();
+        let mut mem = Mapping::new(0x1000).unwrap();
+        assert_eq!(mem.map(0x4000, MemPerms::RX), Ok(()));
+        // Writes a `add x0, x0, #1` instruction at address 0x4000.
+        assert_eq!(mem.write_dword(0x4000, 0x91000400), Ok(4));
+        // Writes a `b 0x4000` instruction at address 0x4004.
+        assert_eq!(mem.write_dword(0x4004, 0x17ffffff), Ok(4));
+        let sched = Scheduler::new(2).unwrap();
+        for thread in sched.vcpus() {
+            assert_eq!(thread.exec(|vcpu| vcpu.set_reg(Reg::PC, 0x4000)), Ok(()));
+        }
+        // Every step advances both vCPUs by the same number of instructions.
+        for step in 1..=3 {
+            let exits = sched.step_all(10).unwrap();
+            assert_eq!(exits.len(), 2);
+            for (thread, (_, count)) in sched.vcpus().iter().zip(exits) {
+                assert_eq!(count, 10);
+                assert_eq!(thread.exec(|vcpu| vcpu.get_reg(Reg::X0)), Ok(step * 5));
+            }
+        }
+        // Nothing is executed with an empty budget.
+        let exits = sched.step_all(0).unwrap();
+        assert!(exits.iter().all(|&(_, count)| count == 0));
+        // A terminated vCPU thread makes the step fail without blocking the other vCPUs.
+        let ret = sched.vcpus()[0].try_exec(|_| -> () { panic!("job failed") });
+        assert_eq!(ret, Err(HypervisorError::Error));
+        assert_eq!(sched.step_all(10), Err(HypervisorError::Error));
+        assert_eq!(sched.vcpus()[1].exec(|vcpu| vcpu.get_reg(Reg::X0)), Ok(20));
+    }
+}